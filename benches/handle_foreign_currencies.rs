@@ -0,0 +1,58 @@
+//! Measures `handle_foreign_currencies` on a large synthetic journal.
+//!
+//! Run with `cargo bench --bench handle_foreign_currencies`.
+
+use ledger_utils::handle_foreign_currencies::handle_foreign_currencies;
+use ledger_utils::prices::Prices;
+use ledger_utils::Ledger;
+use std::fmt::Write;
+use std::time::Instant;
+
+fn synthetic_journal(days: u32) -> String {
+    let mut journal = String::new();
+    let start = chrono::NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    for day in 0..days {
+        let date = start + chrono::Duration::days(day as i64);
+        writeln!(
+            journal,
+            "P {} 00:00:00 EUR 4.{:02} PLN\n\n\
+             {} Salary\n  Assets:EUR  100 EUR\n  Income:Salary\n\n\
+             {} Exchange\n  Assets:PLN  400 PLN\n  Assets:EUR  -100 EUR\n\n\
+             {} Groceries\n  Expenses:Food  10.50 EUR\n  Expenses:Food  20 PLN\n  Assets:Cash\n",
+            date,
+            day % 100,
+            date,
+            date,
+            date
+        )
+        .unwrap();
+    }
+    journal
+}
+
+fn main() {
+    let input = synthetic_journal(20_000);
+    let mut prices = Prices::new();
+    prices.insert_from(&input.parse().unwrap());
+    let ledger: Ledger = input.parse().unwrap();
+
+    let mut result = ledger.clone();
+    let started = Instant::now();
+    handle_foreign_currencies(
+        &mut result,
+        &|account: &str| account.starts_with("Assets"),
+        &|account: &str| account.starts_with("Income"),
+        &|account: &str| account.starts_with("Expenses"),
+        "PLN",
+        2,
+        &prices,
+    )
+    .unwrap();
+    let elapsed = started.elapsed();
+
+    let postings: usize = result.transactions.iter().map(|t| t.postings.len()).sum();
+    println!(
+        "handle_foreign_currencies: {:?} ({} postings)",
+        elapsed, postings
+    );
+}
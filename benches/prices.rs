@@ -0,0 +1,54 @@
+//! Compares `Prices::insert_from` and `Prices::load_parallel` on a large synthetic journal.
+//!
+//! Run with `cargo bench --features rayon`.
+
+use ledger_utils::prices::Prices;
+use std::fmt::Write;
+use std::time::Instant;
+
+fn synthetic_journal(days: u32) -> String {
+    let mut journal = String::new();
+    let start = chrono::NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    for day in 0..days {
+        let date = start + chrono::Duration::days(day as i64);
+        for (i, commodity) in ["EUR", "USD", "CHF", "GBP"].iter().enumerate() {
+            writeln!(
+                journal,
+                "P {} 00:00:00 {} {}.{:02} PLN",
+                date,
+                commodity,
+                3 + i,
+                day % 100
+            )
+            .unwrap();
+        }
+        writeln!(
+            journal,
+            "\n{} Exchange\n  Assets:EUR  10 EUR\n  Assets:PLN  -4{:02}.50 PLN\n",
+            date,
+            day % 100
+        )
+        .unwrap();
+    }
+    journal
+}
+
+fn main() {
+    let ledger: ledger_parser::Ledger = synthetic_journal(50_000).parse().unwrap();
+
+    let started = Instant::now();
+    let mut sequential = Prices::new();
+    sequential.insert_from(&ledger);
+    let sequential_time = started.elapsed();
+
+    let started = Instant::now();
+    let parallel = Prices::load_parallel(&ledger);
+    let parallel_time = started.elapsed();
+
+    assert_eq!(sequential.pairs(), parallel.pairs());
+    for (pair, rates_table) in &sequential.rates {
+        assert_eq!(rates_table.table, parallel.rates[pair].table);
+    }
+    println!("insert_from:   {:?}", sequential_time);
+    println!("load_parallel: {:?}", parallel_time);
+}
@@ -1,17 +1,29 @@
 use crate::prices::{Prices, PricesError};
-use crate::Amount;
+use crate::{Amount, AmountExt};
 use chrono::NaiveDate;
 use rust_decimal::Decimal;
 use rust_decimal::RoundingStrategy;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::AddAssign;
+use std::ops::Mul;
+use std::ops::MulAssign;
+use std::ops::Neg;
 use std::ops::SubAssign;
 
 /// Balance of an single account.
 ///
 /// Maps commodity names to amounts.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::serde_support::SerdeAccountBalance",
+        from = "crate::serde_support::SerdeAccountBalance"
+    )
+)]
 pub struct AccountBalance {
     pub amounts: HashMap<String, Amount>,
 }
@@ -51,18 +63,101 @@ impl AccountBalance {
         Ok(result)
     }
 
+    /// Like `value_in_commodity`, but commodities that can't be converted are skipped.
+    /// Returns the value of the convertible amounts and the names of skipped commodities.
+    pub fn value_in_commodity_partial(
+        &self,
+        commodity_name: &str,
+        date: NaiveDate,
+        prices: &Prices,
+    ) -> (Decimal, Vec<String>) {
+        let mut result = Decimal::new(0, 0);
+        let mut skipped = Vec::new();
+        for amount in self.sorted_amounts() {
+            if amount.commodity.name == commodity_name {
+                result += amount.quantity;
+            } else {
+                match prices.convert(
+                    amount.quantity,
+                    &amount.commodity.name,
+                    commodity_name,
+                    date,
+                ) {
+                    Ok(value) => result += value,
+                    Err(_) => skipped.push(amount.commodity.name.clone()),
+                }
+            }
+        }
+        (result, skipped)
+    }
+
     pub fn value_in_commodity_rounded(
         &self,
         commodity_name: &str,
         decimal_points: u32,
         date: NaiveDate,
         prices: &Prices,
+    ) -> Result<Decimal, PricesError> {
+        self.value_in_commodity_rounded_with(
+            commodity_name,
+            decimal_points,
+            RoundingStrategy::MidpointAwayFromZero,
+            date,
+            prices,
+        )
+    }
+
+    /// Like `value_in_commodity_rounded`, but with the given rounding strategy.
+    pub fn value_in_commodity_rounded_with(
+        &self,
+        commodity_name: &str,
+        decimal_points: u32,
+        rounding_strategy: RoundingStrategy,
+        date: NaiveDate,
+        prices: &Prices,
+    ) -> Result<Decimal, PricesError> {
+        let value = self.value_in_commodity(commodity_name, date, prices)?;
+        Ok(value.round_dp_with_strategy(decimal_points, rounding_strategy))
+    }
+
+    /// Like `value_in_commodity_rounded`, but panics if any conversion fails.
+    pub fn expect_value_in_commodity_rounded(
+        &self,
+        commodity_name: &str,
+        decimal_points: u32,
+        date: NaiveDate,
+        prices: &Prices,
     ) -> Decimal {
-        let assets_value = self.value_in_commodity(commodity_name, date, prices);
-        if let Ok(value) = assets_value {
-            value.round_dp_with_strategy(decimal_points, RoundingStrategy::MidpointAwayFromZero)
-        } else {
-            panic!("{:?}", assets_value);
+        match self.value_in_commodity_rounded(commodity_name, decimal_points, date, prices) {
+            Ok(value) => value,
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    /// Returns amounts sorted by commodity name (and quantity).
+    pub fn sorted_amounts(&self) -> Vec<&Amount> {
+        let mut amounts: Vec<&Amount> = self.amounts.values().collect();
+        amounts.sort_by(|a, b| {
+            a.commodity
+                .name
+                .cmp(&b.commodity.name)
+                .then_with(|| a.quantity.cmp(&b.quantity))
+        });
+        amounts
+    }
+
+    /// Returns a view of amounts ordered by commodity name.
+    pub fn amounts_by_commodity(&self) -> BTreeMap<&str, &Amount> {
+        self.amounts
+            .iter()
+            .map(|(commodity_name, amount)| (commodity_name.as_str(), amount))
+            .collect()
+    }
+
+    /// Flips the sign of all amounts.
+    pub fn negate(&mut self) {
+        for amount in self.amounts.values_mut() {
+            amount.negate();
         }
     }
 
@@ -92,8 +187,7 @@ impl fmt::Display for AccountBalance {
             return Ok(());
         }
 
-        let mut amounts: Vec<_> = self.amounts.values().collect();
-        amounts.sort_by_key(|a| &a.commodity.name);
+        let amounts = self.sorted_amounts();
 
         write!(f, "{}", amounts[0])?;
 
@@ -133,7 +227,10 @@ impl<'a> SubAssign<&'a AccountBalance> for AccountBalance {
             self.amounts
                 .entry(currrency_name.clone())
                 .and_modify(|a| a.quantity -= amount.quantity)
-                .or_insert_with(|| amount.clone());
+                .or_insert_with(|| Amount {
+                    quantity: -amount.quantity,
+                    commodity: amount.commodity.clone(),
+                });
         }
         self.remove_empties();
     }
@@ -144,15 +241,217 @@ impl<'a> SubAssign<&'a Amount> for AccountBalance {
         self.amounts
             .entry(amount.commodity.name.clone())
             .and_modify(|a| a.quantity -= amount.quantity)
-            .or_insert_with(|| amount.clone());
+            .or_insert_with(|| Amount {
+                quantity: -amount.quantity,
+                commodity: amount.commodity.clone(),
+            });
+        self.remove_empties();
+    }
+}
+
+impl MulAssign<Decimal> for AccountBalance {
+    fn mul_assign(&mut self, factor: Decimal) {
+        for amount in self.amounts.values_mut() {
+            amount.quantity *= factor;
+        }
         self.remove_empties();
     }
 }
 
+impl Mul<Decimal> for AccountBalance {
+    type Output = AccountBalance;
+
+    fn mul(mut self, factor: Decimal) -> AccountBalance {
+        self *= factor;
+        self
+    }
+}
+
+impl Neg for AccountBalance {
+    type Output = AccountBalance;
+
+    fn neg(mut self) -> AccountBalance {
+        self.negate();
+        self
+    }
+}
+
+impl Hash for AccountBalance {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for amount in self.sorted_amounts() {
+            amount.commodity.name.hash(state);
+            amount.quantity.hash(state);
+        }
+    }
+}
+
+/// Lists amounts sorted by commodity name, so the output is stable
+/// and can be used in snapshot tests.
 impl fmt::Debug for AccountBalance {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let mut values: Vec<Amount> = self.amounts.values().cloned().collect();
-        values.sort_by(|a, b| a.commodity.name.partial_cmp(&b.commodity.name).unwrap());
-        write!(f, "{:?}", values)
+        write!(f, "{:?}", self.sorted_amounts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+    use crate::{Commodity, CommodityPosition};
+
+    fn amount(quantity: Decimal, commodity_name: &str) -> Amount {
+        Amount {
+            quantity,
+            commodity: Commodity {
+                name: commodity_name.to_string(),
+                position: CommodityPosition::Right,
+            },
+        }
+    }
+
+    fn account_balance(amounts: &[Amount]) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        for amount in amounts {
+            balance += amount;
+        }
+        balance
+    }
+
+    #[test]
+    fn test_mul() {
+        let balance = account_balance(&[
+            amount(Decimal::new(100, 0), "PLN"),
+            amount(Decimal::new(-30, 0), "EUR"),
+        ]);
+
+        let scaled = balance.clone() * Decimal::new(5, 1);
+        assert_eq!(scaled.to_string(), "-15.0 EUR, 50.0 PLN");
+
+        let mut scaled = balance;
+        scaled *= Decimal::ZERO;
+        assert!(scaled.amounts.is_empty());
+    }
+
+    #[test]
+    fn test_sorted_amounts() {
+        let balance = account_balance(&[
+            amount(Decimal::new(1, 0), "PLN"),
+            amount(Decimal::new(2, 0), "EUR"),
+            amount(Decimal::new(3, 0), "ADA"),
+        ]);
+        let names: Vec<_> = balance
+            .sorted_amounts()
+            .iter()
+            .map(|amount| amount.commodity.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["ADA", "EUR", "PLN"]);
+        assert_eq!(
+            balance.amounts_by_commodity().keys().collect::<Vec<_>>(),
+            vec![&"ADA", &"EUR", &"PLN"]
+        );
+    }
+
+    #[test]
+    fn test_value_in_commodity_partial() {
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR 4.00 PLN\n");
+
+        let balance = account_balance(&[
+            amount(Decimal::new(100, 0), "PLN"),
+            amount(Decimal::new(10, 0), "EUR"),
+            amount(Decimal::new(5, 0), "XYZ"),
+        ]);
+        let date = date(2020, 1, 2);
+
+        assert!(balance.value_in_commodity("PLN", date, &prices).is_err());
+        assert_eq!(
+            balance.value_in_commodity_partial("PLN", date, &prices),
+            (Decimal::new(140, 0), vec!["XYZ".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_value_in_commodity_rounded_with() {
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR 4.00 PLN\n");
+
+        let balance = account_balance(&[amount(Decimal::new(56125, 5), "EUR")]);
+        let date = date(2020, 1, 2);
+
+        assert_eq!(
+            balance
+                .value_in_commodity_rounded("PLN", 2, date, &prices)
+                .unwrap(),
+            Decimal::new(225, 2)
+        );
+        assert_eq!(
+            balance
+                .value_in_commodity_rounded_with(
+                    "PLN",
+                    2,
+                    RoundingStrategy::MidpointNearestEven,
+                    date,
+                    &prices
+                )
+                .unwrap(),
+            Decimal::new(224, 2)
+        );
+        assert_eq!(
+            balance
+                .value_in_commodity_rounded_with("PLN", 1, RoundingStrategy::ToZero, date, &prices)
+                .unwrap(),
+            Decimal::new(22, 1)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let balance = account_balance(&[amount(Decimal::new(120, 2), "PLN")]);
+        let json = serde_json::to_string(&balance).unwrap();
+        assert_eq!(json, r#"{"PLN":{"quantity":"1.20","position":"Right"}}"#);
+
+        let deserialized: AccountBalance = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.amounts, balance.amounts);
+    }
+
+    #[test]
+    fn test_negate() {
+        let balance = account_balance(&[
+            amount(Decimal::new(100, 0), "PLN"),
+            amount(Decimal::new(-30, 0), "EUR"),
+        ]);
+        assert_eq!((-balance.clone()).to_string(), "30 EUR, -100 PLN");
+
+        let mut negated = balance;
+        negated.negate();
+        negated.negate();
+        assert_eq!(negated.to_string(), "-30 EUR, 100 PLN");
+    }
+
+    #[test]
+    fn test_eq_hash_debug() {
+        use std::collections::HashSet;
+
+        let a = account_balance(&[
+            amount(Decimal::new(100, 0), "PLN"),
+            amount(Decimal::new(-30, 0), "EUR"),
+        ]);
+        let b = account_balance(&[
+            amount(Decimal::new(-300, 1), "EUR"),
+            amount(Decimal::new(100, 0), "PLN"),
+        ]);
+        assert_eq!(a, b);
+        assert_eq!(HashSet::from([a.clone(), b]).len(), 1);
+        assert_ne!(a, account_balance(&[amount(Decimal::new(100, 0), "PLN")]));
+
+        assert_eq!(
+            format!("{:?}", a),
+            format!(
+                "{:?}",
+                vec![
+                    amount(Decimal::new(-30, 0), "EUR"),
+                    amount(Decimal::new(100, 0), "PLN")
+                ]
+            )
+        );
     }
 }
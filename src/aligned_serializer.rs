@@ -0,0 +1,398 @@
+use crate::{Amount, CommodityPosition, Ledger, Transaction};
+use ledger_parser::SerializerSettings;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+use std::io;
+
+/// How amounts of postings are aligned.
+///
+/// Columns are counted from 1 at the start of the line (including indent).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignMode {
+    /// The amount ends at `column`.
+    Right { column: usize },
+
+    /// The decimal point (or where it would be for whole numbers) is at `column`.
+    /// Amounts with fewer decimals are padded with spaces on the fractional side,
+    /// so commodities written after the quantity line up too.
+    DecimalPoint { column: usize },
+}
+
+impl AlignMode {
+    /// Same alignment for text starting `width` characters to the right.
+    pub(crate) fn shifted(self, width: usize) -> Self {
+        match self {
+            AlignMode::Right { column } => AlignMode::Right {
+                column: column.saturating_sub(width),
+            },
+            AlignMode::DecimalPoint { column } => AlignMode::DecimalPoint {
+                column: column.saturating_sub(width),
+            },
+        }
+    }
+}
+
+/// Separator inserted between groups of digits of the integer part, e.g. `98,900.00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigitGrouping {
+    /// Should differ from `.`, which is the decimal separator.
+    pub separator: char,
+    pub group_size: usize,
+}
+
+/// How amounts of postings are written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountFormat {
+    /// Display precision (decimal places) by commodity name.
+    /// Amounts of other commodities are written with their own scale.
+    pub precisions: HashMap<String, u32>,
+
+    /// No grouping by default, to keep the output machine-readable.
+    pub digit_grouping: Option<DigitGrouping>,
+}
+
+impl AmountFormat {
+    pub fn new() -> Self {
+        AmountFormat::default()
+    }
+
+    pub fn with_precision(mut self, commodity_name: &str, decimal_places: u32) -> Self {
+        self.precisions
+            .insert(commodity_name.to_string(), decimal_places);
+        self
+    }
+
+    pub fn with_digit_grouping(mut self, separator: char, group_size: usize) -> Self {
+        self.digit_grouping = Some(DigitGrouping {
+            separator,
+            group_size,
+        });
+        self
+    }
+
+    /// Returns the amount rounded (midpoint away from zero) or padded with zeros
+    /// to the display precision of its commodity.
+    pub fn rounded(&self, amount: &Amount) -> Amount {
+        let mut amount = amount.clone();
+        if let Some(&decimal_places) = self.precisions.get(&amount.commodity.name) {
+            amount.quantity = amount
+                .quantity
+                .round_dp_with_strategy(decimal_places, RoundingStrategy::MidpointAwayFromZero);
+            amount.quantity.rescale(decimal_places);
+        }
+        amount
+    }
+
+    /// Formats the quantity, inserting digit group separators if configured.
+    /// The quantity is not rounded.
+    pub fn format_quantity(&self, quantity: Decimal) -> String {
+        let text = quantity.to_string();
+        let grouping = match self.digit_grouping {
+            Some(grouping) if grouping.group_size > 0 => grouping,
+            _ => return text,
+        };
+
+        let (sign, unsigned) = match text.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", text.as_str()),
+        };
+        let (integer, fraction) = match unsigned.find('.') {
+            Some(point) => unsigned.split_at(point),
+            None => (unsigned, ""),
+        };
+
+        let mut result = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % grouping.group_size == 0 {
+                result.push(grouping.separator);
+            }
+            result.push(digit);
+        }
+        result.push_str(fraction);
+        result
+    }
+
+    /// Formats the amount, e.g. `$5.00` or `-1.500000 ADA`.
+    pub fn format_amount(&self, amount: &Amount) -> String {
+        let amount = self.rounded(amount);
+        let quantity = self.format_quantity(amount.quantity);
+        match amount.commodity.position {
+            CommodityPosition::Left => format!("{}{}", amount.commodity.name, quantity),
+            CommodityPosition::Right => format!("{} {}", quantity, amount.commodity.name),
+        }
+    }
+}
+
+/// Serializer that aligns amounts of postings to a fixed column,
+/// like the `align` option of hledger's / Paisa's formatter.
+///
+/// Accounts too long to fit are followed by a two-space gap.
+#[derive(Default)]
+pub struct AlignedSerializer {
+    pub settings: SerializerSettings,
+    pub align: Option<AlignMode>,
+    pub amount_format: AmountFormat,
+}
+
+impl AlignedSerializer {
+    pub fn new(settings: SerializerSettings) -> Self {
+        AlignedSerializer {
+            settings,
+            align: None,
+            amount_format: AmountFormat::default(),
+        }
+    }
+
+    /// Right-aligns amounts, so they end at `column`.
+    pub fn with_align_amounts(mut self, column: usize) -> Self {
+        self.align = Some(AlignMode::Right { column });
+        self
+    }
+
+    pub fn with_align_mode(mut self, align: AlignMode) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Writes amounts of the commodity with `decimal_places` decimal places.
+    pub fn with_precision(mut self, commodity_name: &str, decimal_places: u32) -> Self {
+        self.amount_format = self
+            .amount_format
+            .with_precision(commodity_name, decimal_places);
+        self
+    }
+
+    /// Separates groups of `group_size` digits of the integer part with `separator`.
+    pub fn with_digit_grouping(mut self, separator: char, group_size: usize) -> Self {
+        self.amount_format = self
+            .amount_format
+            .with_digit_grouping(separator, group_size);
+        self
+    }
+
+    pub fn write_ledger<W>(&self, writer: &mut W, ledger: &Ledger) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        ledger.write_aligned(writer, &self.settings, self.align, &self.amount_format)
+    }
+
+    pub fn write_transaction<W>(
+        &self,
+        writer: &mut W,
+        transaction: &Transaction,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        transaction.write_aligned(writer, &self.settings, self.align, &self.amount_format)
+    }
+
+    pub fn ledger_to_string(&self, ledger: &Ledger) -> String {
+        let mut res = Vec::new();
+        self.write_ledger(&mut res, ledger).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+
+    pub fn transaction_to_string(&self, transaction: &Transaction) -> String {
+        let mut res = Vec::new();
+        self.write_transaction(&mut res, transaction).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+}
+
+/// Width of the fractional part of the quantity, including the decimal point.
+pub(crate) fn fraction_width(amount: &Amount) -> usize {
+    match amount.quantity.scale() {
+        0 => 0,
+        scale => scale as usize + 1,
+    }
+}
+
+/// Returns the gap to write after the account name and the amount text.
+///
+/// `max_fraction_width` is computed from amounts already rounded by `amount_format`.
+pub(crate) fn format_amount(
+    account_width: usize,
+    amount: &Amount,
+    align: Option<AlignMode>,
+    amount_format: &AmountFormat,
+    max_fraction_width: usize,
+) -> (usize, String) {
+    match align {
+        None => (2, amount_format.format_amount(amount)),
+        Some(AlignMode::Right { column }) => {
+            let text = amount_format.format_amount(amount);
+            let gap = column.saturating_sub(account_width + text.chars().count());
+            (gap.max(2), text)
+        }
+        Some(AlignMode::DecimalPoint { column }) => {
+            let amount = &amount_format.rounded(amount);
+            let quantity = amount_format.format_quantity(amount.quantity);
+            let integer_width = quantity.chars().count() - fraction_width(amount);
+            let padding = max_fraction_width.saturating_sub(fraction_width(amount));
+
+            let (text, point_offset) = match amount.commodity.position {
+                CommodityPosition::Left => (
+                    format!("{}{}", amount.commodity.name, quantity),
+                    amount.commodity.name.chars().count() + integer_width,
+                ),
+                CommodityPosition::Right => (
+                    format!("{}{:padding$} {}", quantity, "", amount.commodity.name),
+                    integer_width,
+                ),
+            };
+            let gap = column.saturating_sub(account_width + point_offset + 1);
+            (gap.max(2), text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_amounts() {
+        let ledger: Ledger = r#"
+2023-01-05 Groceries
+  expenses:groceries  98,900 IDR
+  assets:checking:idr  -98,900 IDR
+
+2023-01-06 Rent
+  ! expenses:rent:a:very:long:account:name:that:does:not:fit  1,234.56 IDR
+  assets:checking:idr
+"#
+        .parse()
+        .unwrap();
+
+        let expected = r#"2023-01-05 Groceries
+  expenses:groceries                       98900 IDR
+  assets:checking:idr                     -98900 IDR
+
+2023-01-06 Rent
+  ! expenses:rent:a:very:long:account:name:that:does:not:fit  1234.56 IDR
+  assets:checking:idr                   -1234.56 IDR
+"#;
+        let serializer =
+            AlignedSerializer::new(SerializerSettings::default()).with_align_amounts(52);
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+        assert_eq!(expected.lines().nth(1).unwrap().chars().count(), 52);
+
+        let serializer = AlignedSerializer::new(SerializerSettings::default())
+            .with_align_mode(AlignMode::DecimalPoint { column: 46 });
+        let expected = r#"2023-01-05 Groceries
+  expenses:groceries                    98900 IDR
+  assets:checking:idr                  -98900 IDR
+
+2023-01-06 Rent
+  ! expenses:rent:a:very:long:account:name:that:does:not:fit  1234.56 IDR
+  assets:checking:idr                   -1234.56 IDR
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+
+        let ledger: Ledger = r#"
+2023-01-07 Exchange
+  assets:usd  $1.5
+  assets:usd  $-0.25
+  assets:usd  $-1.25
+"#
+        .parse()
+        .unwrap();
+        let expected = r#"2023-01-07 Exchange
+  assets:usd                               $1.5
+  assets:usd                              $-0.25
+  assets:usd                              $-1.25
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+
+        let ledger: Ledger = r#"
+2023-01-08 Exchange
+  assets:eur  100 EUR
+  assets:eur  -99.75 EUR
+  assets:eur
+"#
+        .parse()
+        .unwrap();
+        let expected = r#"2023-01-08 Exchange
+  assets:eur                              100    EUR
+  assets:eur                              -99.75 EUR
+  assets:eur                               -0.25 EUR
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+
+        let serializer = AlignedSerializer::default();
+        assert_eq!(serializer.ledger_to_string(&ledger), ledger.to_string());
+    }
+
+    #[test]
+    fn test_precision() {
+        let ledger: Ledger = r#"
+2023-01-05 Staking
+  assets:ada  1.5 ADA
+  assets:usd  $5
+  assets:eur  1.005 EUR
+  income:staking
+"#
+        .parse()
+        .unwrap();
+
+        let serializer = AlignedSerializer::new(SerializerSettings::default())
+            .with_precision("$", 2)
+            .with_precision("ADA", 6)
+            .with_precision("EUR", 2);
+        let expected = r#"2023-01-05 Staking
+  assets:ada  1.500000 ADA
+  assets:usd  $5.00
+  assets:eur  1.01 EUR
+  income:staking  $-5.00
+  income:staking  -1.500000 ADA
+  income:staking  -1.01 EUR
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+
+        let serializer = serializer.with_align_mode(AlignMode::DecimalPoint { column: 24 });
+        let expected = r#"2023-01-05 Staking
+  assets:ada          1.500000 ADA
+  assets:usd         $5.00
+  assets:eur          1.01     EUR
+  income:staking    $-5.00
+  income:staking     -1.500000 ADA
+  income:staking     -1.01     EUR
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+    }
+
+    #[test]
+    fn test_digit_grouping() {
+        let input = r#"2023-01-05 Groceries
+  expenses:groceries  98,900.00 IDR
+  assets:checking:idr  -1,234,567.5 IDR
+  assets:usd  $-999
+  assets:usd
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let serializer =
+            AlignedSerializer::new(SerializerSettings::default()).with_digit_grouping(',', 3);
+        let expected = r#"2023-01-05 Groceries
+  expenses:groceries  98,900.00 IDR
+  assets:checking:idr  -1,234,567.5 IDR
+  assets:usd  $-999
+  assets:usd  $999
+  assets:usd  1,135,667.50 IDR
+"#;
+        let output = serializer.ledger_to_string(&ledger);
+        assert_eq!(output, expected);
+        assert_eq!(output.parse::<Ledger>().unwrap(), ledger);
+
+        let serializer = serializer.with_align_mode(AlignMode::DecimalPoint { column: 34 });
+        let expected = r#"2023-01-05 Groceries
+  expenses:groceries       98,900.00 IDR
+  assets:checking:idr  -1,234,567.5  IDR
+  assets:usd                $-999
+  assets:usd                 $999
+  assets:usd            1,135,667.50 IDR
+"#;
+        assert_eq!(serializer.ledger_to_string(&ledger), expected);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::Amount;
+use rust_decimal::Decimal;
+
+/// Arithmetic helpers for `Amount`.
+pub trait AmountExt {
+    /// Flips the sign of the quantity.
+    fn negate(&mut self);
+
+    /// Returns a copy with flipped sign of the quantity.
+    fn negated(&self) -> Amount;
+
+    fn is_zero(&self) -> bool;
+
+    /// Whether both amounts are in the commodity with the same name.
+    fn same_commodity(&self, other: &Amount) -> bool;
+
+    /// Returns the sum, or `None` if the commodities differ.
+    fn checked_add(&self, other: &Amount) -> Option<Amount>;
+}
+
+impl AmountExt for Amount {
+    fn negate(&mut self) {
+        self.quantity = -self.quantity;
+    }
+
+    fn negated(&self) -> Amount {
+        Amount {
+            quantity: -self.quantity,
+            commodity: self.commodity.clone(),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.quantity == Decimal::ZERO
+    }
+
+    fn same_commodity(&self, other: &Amount) -> bool {
+        self.commodity.name == other.commodity.name
+    }
+
+    fn checked_add(&self, other: &Amount) -> Option<Amount> {
+        if !self.same_commodity(other) {
+            return None;
+        }
+        Some(Amount {
+            quantity: self.quantity + other.quantity,
+            commodity: self.commodity.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Commodity, CommodityPosition};
+
+    fn amount(quantity: Decimal, commodity_name: &str) -> Amount {
+        Amount {
+            quantity,
+            commodity: Commodity {
+                name: commodity_name.to_string(),
+                position: CommodityPosition::Right,
+            },
+        }
+    }
+
+    #[test]
+    fn test_amount_ext() {
+        let mut a = amount(Decimal::new(10, 0), "PLN");
+        let b = amount(Decimal::new(-10, 0), "PLN");
+        let c = amount(Decimal::new(10, 0), "EUR");
+
+        assert!(a.same_commodity(&b));
+        assert!(!a.same_commodity(&c));
+        assert!(a.checked_add(&b).unwrap().is_zero());
+        assert_eq!(a.checked_add(&c), None);
+        assert_eq!(a.negated(), b);
+
+        a.negate();
+        assert_eq!(a, b);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::account_balance::AccountBalance;
-use crate::{Amount, Ledger, Transaction};
-use std::collections::HashMap;
+use crate::calculate_amounts;
+use crate::{Amount, Error, Ledger, Posting, Transaction, TransactionStatus};
+use chrono::NaiveDate;
+use ledger_parser::LedgerItem;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::ops::AddAssign;
 use std::ops::SubAssign;
 
@@ -8,10 +12,37 @@ use std::ops::SubAssign;
 ///
 /// Maps account names to their balances.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Balance {
     pub account_balances: HashMap<String, AccountBalance>,
 }
 
+/// Difference of a single account between two balances.
+#[derive(Debug, Clone)]
+pub struct AccountDiff {
+    pub account: String,
+    pub per_commodity_delta: AccountBalance,
+}
+
+/// Which posting date is used when filtering by date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateMode {
+    /// The posting date (cash basis).
+    #[default]
+    Posting,
+    /// The effective posting date (accrual basis).
+    Effective,
+}
+
+impl DateMode {
+    pub fn date_of(&self, posting: &Posting) -> NaiveDate {
+        match self {
+            DateMode::Posting => posting.date,
+            DateMode::Effective => posting.effective_date,
+        }
+    }
+}
+
 impl Default for Balance {
     fn default() -> Self {
         Self::new()
@@ -25,8 +56,118 @@ impl Balance {
         }
     }
 
+    /// Balance of postings dated on or before `date`.
+    pub fn as_of(ledger: &Ledger, date: NaiveDate, date_mode: DateMode) -> Balance {
+        let mut balance = Balance::new();
+        for transaction in &ledger.transactions {
+            balance.update_with_postings(transaction, |posting| date_mode.date_of(posting) <= date);
+        }
+        balance
+    }
+
+    /// Balance of postings dated within `[start, end]`.
+    pub fn in_range(
+        ledger: &Ledger,
+        start: NaiveDate,
+        end: NaiveDate,
+        date_mode: DateMode,
+    ) -> Balance {
+        let mut balance = Balance::new();
+        for transaction in &ledger.transactions {
+            balance.update_with_postings(transaction, |posting| {
+                (start..=end).contains(&date_mode.date_of(posting))
+            });
+        }
+        balance
+    }
+
+    /// Balance of postings whose status passes `status_filter`.
+    ///
+    /// Posting status overrides the status of its transaction.
+    pub fn from_status<F>(ledger: &Ledger, status_filter: F) -> Balance
+    where
+        F: Fn(Option<TransactionStatus>) -> bool,
+    {
+        let mut balance = Balance::new();
+        for transaction in &ledger.transactions {
+            balance.update_with_postings(transaction, |posting| {
+                status_filter(posting.status.or(transaction.status))
+            });
+        }
+        balance
+    }
+
+    /// Walks transactions in order, maintaining running account balances,
+    /// and checks every balance assertion (posting with both amount and `= balance`).
+    ///
+    /// Fails with `Error::BalanceAssertionFailed` (or `Error::ZeroBalanceAssertionFailed`)
+    /// for the first offending transaction. Unbalanced transactions are reported as well.
+    pub fn verify_assertions(ledger: &ledger_parser::Ledger) -> Result<(), Error> {
+        let mut transactions: Vec<ledger_parser::Transaction> = ledger
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                LedgerItem::Transaction(transaction) => Some(transaction.clone()),
+                _ => None,
+            })
+            .collect();
+
+        calculate_amounts::calculate_amounts_from_balances(&mut transactions, &mut Vec::new())
+    }
+
     pub fn update_with_transaction(&mut self, transaction: &Transaction) {
+        self.update_with_postings(transaction, |_| true);
+    }
+
+    /// Adds postings of the transaction, touching only their accounts,
+    /// so it's cheap to keep a balance up to date when a single transaction changes.
+    ///
+    /// Amounts and accounts that become zero are removed, so `unapply`
+    /// of the same transaction restores the previous balance.
+    pub fn apply(&mut self, transaction: &Transaction) {
+        self.add_postings(transaction, false);
+    }
+
+    /// Reverts `apply` of the transaction.
+    pub fn unapply(&mut self, transaction: &Transaction) {
+        self.add_postings(transaction, true);
+    }
+
+    fn add_postings(&mut self, transaction: &Transaction, negate: bool) {
+        for posting in &transaction.postings {
+            let account_balance = self
+                .account_balances
+                .entry(posting.account.clone())
+                .or_default();
+            if negate {
+                *account_balance -= &posting.amount;
+            } else {
+                *account_balance += &posting.amount;
+            }
+        }
+
         for posting in &transaction.postings {
+            if let Some(account_balance) = self.account_balances.get_mut(&posting.account) {
+                account_balance
+                    .amounts
+                    .retain(|_, amount| !amount.quantity.is_zero());
+                if account_balance.amounts.is_empty() {
+                    self.account_balances.remove(&posting.account);
+                }
+            }
+        }
+    }
+
+    /// Updates the balance with postings of the transaction that pass the `filter`.
+    pub fn update_with_postings<F>(&mut self, transaction: &Transaction, filter: F)
+    where
+        F: Fn(&Posting) -> bool,
+    {
+        for posting in transaction
+            .postings
+            .iter()
+            .filter(|posting| filter(posting))
+        {
             let account_balance = self
                 .account_balances
                 .entry(posting.account.clone())
@@ -41,6 +182,8 @@ impl Balance {
         self.remove_empties();
     }
 
+    /// Sums balances of accounts starting with any of `account_prefixes`.
+    /// Scans all accounts; for many queries on the same balance use `BalanceIndex`.
     pub fn get_account_balance(&self, account_prefixes: &[&str]) -> AccountBalance {
         let mut balance = AccountBalance::new();
         for (account_name, account_balance) in &self.account_balances {
@@ -55,12 +198,70 @@ impl Balance {
         balance
     }
 
+    /// Sums balances of accounts starting with one of `include` prefixes,
+    /// but not with any of `exclude` prefixes. Exclusion takes precedence.
+    pub fn get_account_balance_excluding(
+        &self,
+        include: &[&str],
+        exclude: &[&str],
+    ) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        for (account_name, account_balance) in &self.account_balances {
+            if include
+                .iter()
+                .any(|prefix| account_name.starts_with(prefix))
+                && !exclude
+                    .iter()
+                    .any(|prefix| account_name.starts_with(prefix))
+            {
+                balance += account_balance;
+            }
+        }
+
+        balance
+    }
+
+    /// Sums balances of all accounts whose name matches `pattern`.
+    ///
+    /// Unlike prefixes in `get_account_balance`, the pattern is not anchored.
+    /// Every account is counted at most once, so to match multiple patterns
+    /// combine them into one with `|` instead of summing separate results.
+    #[cfg(feature = "regex")]
+    pub fn get_account_balance_matching(&self, pattern: &regex::Regex) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        for (account_name, account_balance) in &self.account_balances {
+            if pattern.is_match(account_name) {
+                balance += account_balance;
+            }
+        }
+
+        balance
+    }
+
+    /// Compares two balances. Returns `self - other` for every account present
+    /// in either balance, sorted by account name. Accounts without difference are omitted.
+    pub fn diff(&self, other: &Balance) -> Vec<AccountDiff> {
+        let mut delta = self.clone();
+        delta -= other;
+
+        let mut result: Vec<AccountDiff> = delta
+            .account_balances
+            .into_iter()
+            .map(|(account, per_commodity_delta)| AccountDiff {
+                account,
+                per_commodity_delta,
+            })
+            .collect();
+        result.sort_by(|a, b| a.account.cmp(&b.account));
+        result
+    }
+
     pub fn add_amount(&mut self, account: &str, amount: &Amount) {
         let account_balance = self.account_balances.entry(account.to_owned()).or_default();
         *account_balance += amount;
     }
 
-    fn remove_empties(&mut self) {
+    pub(crate) fn remove_empties(&mut self) {
         let empties: Vec<String> = self
             .account_balances
             .iter()
@@ -73,6 +274,61 @@ impl Balance {
     }
 }
 
+/// One line per commodity with amounts aligned to the right,
+/// followed by the account name on the first line of every account.
+/// Accounts and commodities are sorted.
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut accounts: Vec<_> = self.account_balances.iter().collect();
+        accounts.sort_by_key(|&(account_name, _)| account_name);
+
+        let lines: Vec<(String, &str)> = accounts
+            .into_iter()
+            .flat_map(|(account_name, account_balance)| {
+                account_balance
+                    .sorted_amounts()
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, amount)| {
+                        let account_name = if i == 0 { account_name.as_str() } else { "" };
+                        (amount.to_string(), account_name)
+                    })
+            })
+            .collect();
+
+        let width = lines
+            .iter()
+            .map(|(amount, _)| amount.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (amount, account_name) in lines {
+            if account_name.is_empty() {
+                writeln!(f, "{:>width$}", amount)?;
+            } else {
+                writeln!(f, "{:>width$}  {}", amount, account_name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sums postings grouped by the value of their tag named `tag_name`.
+///
+/// Tags without value are grouped under an empty string,
+/// postings without the tag under `None`.
+pub fn balance_by_tag(ledger: &Ledger, tag_name: &str) -> HashMap<Option<String>, AccountBalance> {
+    let mut result: HashMap<Option<String>, AccountBalance> = HashMap::new();
+    for (_, _, posting) in ledger.postings() {
+        let key = posting.tag(tag_name).map(|tag| match tag.value {
+            Some(ref value) => value.to_string(),
+            None => String::new(),
+        });
+        *result.entry(key).or_default() += &posting.amount;
+    }
+    result
+}
+
 impl<'a> From<&'a Ledger> for Balance {
     fn from(ledger: &'a Ledger) -> Self {
         let mut balance = Balance::new();
@@ -108,11 +364,404 @@ impl<'a> AddAssign<&'a Balance> for Balance {
 impl<'a> SubAssign<&'a Balance> for Balance {
     fn sub_assign(&mut self, other: &'a Balance) {
         for (account_name, account_balance) in &other.account_balances {
-            self.account_balances
+            *self
+                .account_balances
                 .entry(account_name.clone())
-                .and_modify(|b| *b -= account_balance)
-                .or_insert_with(|| account_balance.clone());
+                .or_default() -= account_balance;
         }
         self.remove_empties();
     }
 }
+
+/// Index of a `Balance` for fast `get_account_balance` queries.
+///
+/// Accounts are stored in a tree of name segments (split by `:`), each node holding
+/// the total of its subtree, so a query only walks the segments of the prefix
+/// instead of scanning all accounts.
+///
+/// Building the index costs about as much as a few `Balance::get_account_balance` calls,
+/// so it pays off for reports querying the same balance many times.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceIndex {
+    root: BalanceIndexNode,
+}
+
+#[derive(Debug, Clone, Default)]
+struct BalanceIndexNode {
+    total: AccountBalance,
+    children: BTreeMap<String, BalanceIndexNode>,
+}
+
+impl BalanceIndex {
+    pub fn new(balance: &Balance) -> Self {
+        let mut root = BalanceIndexNode::default();
+        for (account_name, account_balance) in &balance.account_balances {
+            let mut node = &mut root;
+            node.total += account_balance;
+            for segment in account_name.split(':') {
+                node = node.children.entry(segment.to_string()).or_default();
+                node.total += account_balance;
+            }
+        }
+
+        BalanceIndex { root }
+    }
+
+    /// Same as `Balance::get_account_balance`: prefixes are plain string prefixes
+    /// (`Expenses:Food` also matches `Expenses:Foodstuff`) and every account
+    /// is counted at most once.
+    pub fn get_account_balance(&self, account_prefixes: &[&str]) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        for (i, prefix) in account_prefixes.iter().enumerate() {
+            // skip prefixes covered by another one, so that accounts are not counted twice
+            let is_covered = account_prefixes.iter().enumerate().any(|(j, other)| {
+                prefix.starts_with(other) && (prefix.len() > other.len() || j < i)
+            });
+            if !is_covered {
+                self.add_prefix_balance(prefix, &mut balance);
+            }
+        }
+
+        balance
+    }
+
+    fn add_prefix_balance(&self, prefix: &str, balance: &mut AccountBalance) {
+        let (parents, last) = match prefix.rsplit_once(':') {
+            Some((parents, last)) => (Some(parents), last),
+            None => (None, prefix),
+        };
+
+        let mut node = &self.root;
+        for segment in parents.into_iter().flat_map(|parents| parents.split(':')) {
+            match node.children.get(segment) {
+                Some(child) => node = child,
+                None => return,
+            }
+        }
+
+        // the last segment may be incomplete
+        for (name, child) in node.children.range(last.to_string()..) {
+            if !name.starts_with(last) {
+                break;
+            }
+            *balance += &child.total;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_unapply() {
+        let ledger: Ledger = r#"
+2020-01-01 Opening
+  Assets:Bank  $100
+  Equity:Opening
+
+2020-01-02 Exchange
+  Assets:Bank  $-50
+  Assets:Euro  45 EUR
+  Equity:Trading  $50
+  Equity:Trading  -45 EUR
+"#
+        .parse()
+        .unwrap();
+        let opening = Balance::from(&ledger.transactions[0]);
+
+        let mut balance = opening.clone();
+        balance.apply(&ledger.transactions[1]);
+        assert_eq!(
+            balance.account_balances,
+            Balance::from(&ledger).account_balances
+        );
+
+        balance.unapply(&ledger.transactions[1]);
+        assert_eq!(balance.account_balances, opening.account_balances);
+        assert!(!balance.account_balances.contains_key("Equity:Trading"));
+
+        balance.unapply(&ledger.transactions[0]);
+        assert!(balance.account_balances.is_empty());
+    }
+
+    #[test]
+    fn test_balance_index() {
+        let ledger: Ledger = r#"
+2020-01-01 Shopping
+  Expenses  $1
+  Expenses:Food  $10
+  Expenses:Food:Lunch  $5
+  Expenses:Foodstuff  $2
+  Expenses:Rent  20 EUR
+  Assets:Bank  $-18
+  Assets:Cash  -20 EUR
+"#
+        .parse()
+        .unwrap();
+        let balance = Balance::from(&ledger);
+        let index = BalanceIndex::new(&balance);
+
+        for prefixes in [
+            &[""][..],
+            &["Expenses"],
+            &["Expenses:"],
+            &["Expenses:Food"],
+            &["Expenses:Food:"],
+            &["Exp", "Assets:Ca"],
+            &["Expenses", "Expenses:Food", "Expenses"],
+            &["Expenses:Rent", "Assets"],
+            &["Income", "Expenses:Food:Lunch:X"],
+            &[],
+        ] {
+            assert_eq!(
+                index.get_account_balance(prefixes),
+                balance.get_account_balance(prefixes),
+                "prefixes: {:?}",
+                prefixes
+            );
+        }
+        assert_eq!(
+            index.get_account_balance(&["Expenses:Food"]).to_string(),
+            "$17"
+        );
+    }
+
+    #[test]
+    fn test_balance_by_tag() {
+        let ledger: Ledger = r#"
+2020-01-01 Hotel
+  Expenses:Travel  $100  ; project: foo
+  Assets:Bank
+
+2020-01-03 Taxi
+  Expenses:Travel  $20  ; project: bar
+  Expenses:Travel  10 EUR  ; project: foo
+  Assets:Bank  $-20
+  Assets:Cash  -10 EUR
+"#
+        .parse()
+        .unwrap();
+
+        let balances = balance_by_tag(&ledger, "project");
+        assert_eq!(balances.len(), 3);
+        assert_eq!(
+            balances[&Some("foo".to_string())].to_string(),
+            "$100, 10 EUR"
+        );
+        assert_eq!(balances[&Some("bar".to_string())].to_string(), "$20");
+        assert_eq!(balances[&None].to_string(), "$-120, -10 EUR");
+    }
+
+    #[test]
+    fn test_from_status() {
+        let ledger: Ledger = r#"
+2020-01-15 * Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-20 ! Rent
+  Expenses:Rent  $50
+  * Assets:Bank  $-30
+  Assets:Bank
+
+2020-01-25 Food
+  Expenses:Food  $5
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let cleared =
+            Balance::from_status(&ledger, |status| status == Some(TransactionStatus::Cleared));
+        assert_eq!(cleared.account_balances["Assets:Bank"].to_string(), "$70");
+        assert!(!cleared.account_balances.contains_key("Expenses:Rent"));
+
+        let pending =
+            Balance::from_status(&ledger, |status| status == Some(TransactionStatus::Pending));
+        assert_eq!(pending.account_balances["Assets:Bank"].to_string(), "$-20");
+        assert_eq!(pending.account_balances["Expenses:Rent"].to_string(), "$50");
+        assert!(!pending.account_balances.contains_key("Expenses:Food"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let actual: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let budget: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-20 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let diff = Balance::from(&actual).diff(&Balance::from(&budget));
+        let diff: Vec<_> = diff
+            .iter()
+            .map(|d| (d.account.as_str(), d.per_commodity_delta.to_string()))
+            .collect();
+        assert_eq!(
+            diff,
+            vec![
+                ("Assets:Bank", "$45".to_string()),
+                ("Expenses:Food", "$5".to_string()),
+                ("Expenses:Rent", "$-50".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_as_of_and_in_range() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-03-31=2020-04-02 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+
+2020-04-10 Food
+  Expenses:Food  $5
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let date = |m, d| NaiveDate::from_ymd_opt(2020, m, d).unwrap();
+
+        let balance = Balance::as_of(&ledger, date(3, 31), DateMode::Posting);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$50");
+        let balance = Balance::as_of(&ledger, date(3, 31), DateMode::Effective);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$100");
+
+        let balance = Balance::in_range(&ledger, date(4, 1), date(4, 30), DateMode::Posting);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$-5");
+        let balance = Balance::in_range(&ledger, date(4, 1), date(4, 30), DateMode::Effective);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$-55");
+    }
+
+    #[test]
+    fn test_verify_assertions() {
+        let ledger: ledger_parser::Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100 = $100
+  Income:Salary
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank  $-5 = $95
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(Balance::verify_assertions(&ledger), Ok(()));
+
+        let ledger: ledger_parser::Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100 = $100
+  Income:Salary
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank  $-5 = $90
+"#
+        .parse()
+        .unwrap();
+        assert!(matches!(
+            Balance::verify_assertions(&ledger),
+            Err(Error::BalanceAssertionFailed(transaction))
+                if transaction.description == "Food"
+        ));
+    }
+
+    #[test]
+    fn display_balance() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Assets:Cash  5 EUR
+  Assets:Cash  -10 PLN
+  Income:Salary  $-100
+  Income:Salary  -5 EUR
+  Income:Salary  10 PLN
+"#
+        .parse()
+        .unwrap();
+
+        let expected = r#"   $100  Assets:Bank
+  5 EUR  Assets:Cash
+-10 PLN
+  $-100  Income:Salary
+ -5 EUR
+ 10 PLN
+"#;
+        assert_eq!(Balance::from(&ledger).to_string(), expected);
+    }
+
+    #[test]
+    fn test_get_account_balance_excluding() {
+        let ledger: Ledger = r#"
+2020-01-15 Spending
+  Expenses:Food  $10
+  Expenses:Tax  $20
+  Expenses:Tax:Property  $30
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let balance = Balance::from(&ledger);
+
+        assert_eq!(
+            balance
+                .get_account_balance_excluding(&["Expenses"], &["Expenses:Tax"])
+                .to_string(),
+            "$10"
+        );
+        assert_eq!(
+            balance
+                .get_account_balance_excluding(&["Expenses:Tax"], &["Expenses"])
+                .to_string(),
+            "0"
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_get_account_balance_matching() {
+        let ledger: Ledger = r#"
+2020-01-15 Savings
+  Assets:Bank1:Savings  $100
+  Assets:Bank2:Savings  $50
+  Assets:Bank1:Checking  $10
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+        let balance = Balance::from(&ledger);
+
+        let pattern = regex::Regex::new(":Savings$").unwrap();
+        assert_eq!(
+            balance.get_account_balance_matching(&pattern).to_string(),
+            "$150"
+        );
+
+        let pattern = regex::Regex::new("^Assets:.*:(Savings|Checking)$").unwrap();
+        assert_eq!(
+            balance.get_account_balance_matching(&pattern).to_string(),
+            "$160"
+        );
+    }
+}
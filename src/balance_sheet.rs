@@ -0,0 +1,160 @@
+use crate::balance::{Balance, DateMode};
+use crate::prices::{Prices, PricesError};
+use crate::Ledger;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Account prefixes used to build a balance sheet.
+#[derive(Debug, Clone)]
+pub struct BalanceSheetConfig {
+    pub asset_prefixes: Vec<String>,
+    pub liability_prefixes: Vec<String>,
+    pub equity_prefixes: Vec<String>,
+
+    /// Income and expense accounts. As long as they are not closed to equity,
+    /// their balance is reported as `retained_earnings`.
+    pub earnings_prefixes: Vec<String>,
+}
+
+impl Default for BalanceSheetConfig {
+    fn default() -> Self {
+        BalanceSheetConfig {
+            asset_prefixes: vec!["Assets".to_string()],
+            liability_prefixes: vec!["Liabilities".to_string()],
+            equity_prefixes: vec!["Equity".to_string()],
+            earnings_prefixes: vec!["Income".to_string(), "Expenses".to_string()],
+        }
+    }
+}
+
+/// Assets, liabilities and equity at a given date, valued in a single commodity.
+///
+/// Liabilities, equity and retained earnings are reported as positive numbers
+/// (ledger keeps them negative).
+#[derive(Debug, Clone)]
+pub struct BalanceSheet {
+    pub as_of: NaiveDate,
+    pub commodity: String,
+    pub assets: Decimal,
+    pub liabilities: Decimal,
+    pub equity: Decimal,
+    pub retained_earnings: Decimal,
+}
+
+impl BalanceSheet {
+    /// Returns `assets - (liabilities + equity + retained_earnings)`.
+    pub fn imbalance(&self) -> Decimal {
+        self.assets - (self.liabilities + self.equity + self.retained_earnings)
+    }
+
+    /// Checks that assets equal liabilities plus equity within `epsilon`.
+    /// Fails with the imbalance otherwise.
+    pub fn check(&self, epsilon: Decimal) -> Result<(), Decimal> {
+        let imbalance = self.imbalance();
+        if imbalance.abs() <= epsilon {
+            Ok(())
+        } else {
+            Err(imbalance)
+        }
+    }
+}
+
+/// Builds a balance sheet with the default `Assets`, `Liabilities` and `Equity` prefixes.
+pub fn balance_sheet(
+    ledger: &Ledger,
+    as_of: NaiveDate,
+    commodity: &str,
+    prices: &Prices,
+) -> Result<BalanceSheet, PricesError> {
+    balance_sheet_with_config(
+        ledger,
+        as_of,
+        commodity,
+        prices,
+        &BalanceSheetConfig::default(),
+    )
+}
+
+/// Builds a balance sheet from postings dated on or before `as_of`,
+/// valued at prices from that date.
+pub fn balance_sheet_with_config(
+    ledger: &Ledger,
+    as_of: NaiveDate,
+    commodity: &str,
+    prices: &Prices,
+    config: &BalanceSheetConfig,
+) -> Result<BalanceSheet, PricesError> {
+    let balance = Balance::as_of(ledger, as_of, DateMode::Posting);
+
+    let value_of = |prefixes: &[String]| {
+        let prefixes: Vec<&str> = prefixes.iter().map(|prefix| prefix.as_str()).collect();
+        balance
+            .get_account_balance(&prefixes)
+            .value_in_commodity(commodity, as_of, prices)
+    };
+
+    Ok(BalanceSheet {
+        as_of,
+        commodity: commodity.to_string(),
+        assets: value_of(&config.asset_prefixes)?,
+        liabilities: -value_of(&config.liability_prefixes)?,
+        equity: -value_of(&config.equity_prefixes)?,
+        retained_earnings: -value_of(&config.earnings_prefixes)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+
+    const INPUT: &str = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+2020-01-01 Opening balance
+  Assets:Bank  1000 PLN
+  Assets:Savings  100 EUR
+  Equity:Opening
+
+2020-01-10 Credit card
+  Expenses:Food  200 PLN
+  Liabilities:Card
+
+2020-01-15 Salary
+  Assets:Bank  3000 PLN
+  Income:Salary
+
+2020-02-01 Salary
+  Assets:Bank  3000 PLN
+  Income:Salary
+"#;
+
+    #[test]
+    fn test_balance_sheet() {
+        let ledger: Ledger = INPUT.parse().unwrap();
+        let prices = prices_from(INPUT);
+
+        let sheet = balance_sheet(&ledger, date(2020, 1, 31), "PLN", &prices).unwrap();
+        assert_eq!(sheet.assets, Decimal::new(4400, 0));
+        assert_eq!(sheet.liabilities, Decimal::new(200, 0));
+        assert_eq!(sheet.equity, Decimal::new(1400, 0));
+        assert_eq!(sheet.retained_earnings, Decimal::new(2800, 0));
+        assert_eq!(sheet.check(Decimal::ZERO), Ok(()));
+    }
+
+    #[test]
+    fn test_balance_sheet_imbalance() {
+        let ledger: Ledger = INPUT.parse().unwrap();
+        let prices = prices_from(INPUT);
+
+        // salary account not covered by any prefix
+        let config = BalanceSheetConfig {
+            earnings_prefixes: vec!["Expenses".to_string()],
+            ..BalanceSheetConfig::default()
+        };
+        let sheet =
+            balance_sheet_with_config(&ledger, date(2020, 1, 31), "PLN", &prices, &config).unwrap();
+        assert_eq!(sheet.retained_earnings, Decimal::new(-200, 0));
+        assert_eq!(sheet.check(Decimal::new(1, 2)), Err(Decimal::new(3000, 0)));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{Amount, Ledger, Posting, Transaction, TransactionStatus};
+use std::collections::HashMap;
+use std::io;
+
+/// Serializer writing a ledger in Beancount syntax.
+///
+/// Account names are rewritten to Beancount's capitalized form and commodities
+/// are mapped to currency codes with `currencies` (unmapped ones are upper-cased).
+/// A description like `payee | narration` is split into payee and narration.
+/// Standalone comments and tags are not written.
+#[derive(Debug, Clone, Default)]
+pub struct BeancountSerializer {
+    pub currencies: HashMap<String, String>,
+}
+
+impl BeancountSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `commodity` (e.g. `$`) as `currency` (e.g. `USD`).
+    pub fn with_currency(mut self, commodity: &str, currency: &str) -> Self {
+        self.currencies
+            .insert(commodity.to_string(), currency.to_string());
+        self
+    }
+
+    pub fn write_ledger<W>(&self, writer: &mut W, ledger: &Ledger) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut first = true;
+
+        for commodity_price in &ledger.commodity_prices {
+            writeln!(
+                writer,
+                "{} price {} {}",
+                commodity_price.datetime.date().format("%Y-%m-%d"),
+                self.currency(&commodity_price.commodity_name),
+                self.amount(&commodity_price.amount)
+            )?;
+            first = false;
+        }
+
+        for transaction in &ledger.transactions {
+            if !first {
+                writeln!(writer)?;
+            }
+            self.write_transaction(writer, transaction)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_transaction<W>(
+        &self,
+        writer: &mut W,
+        transaction: &Transaction,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        if let Some(ref comment) = transaction.comment {
+            for line in comment.lines() {
+                writeln!(writer, "; {}", line)?;
+            }
+        }
+
+        write!(
+            writer,
+            "{} {}",
+            transaction.date.format("%Y-%m-%d"),
+            flag(transaction.status).unwrap_or("txn")
+        )?;
+        match transaction.description.split_once(" | ") {
+            Some((payee, narration)) => write!(
+                writer,
+                " {} {}",
+                quoted(payee.trim()),
+                quoted(narration.trim())
+            )?,
+            None => write!(writer, " {}", quoted(&transaction.description))?,
+        }
+        writeln!(writer)?;
+
+        for posting in &transaction.postings {
+            self.write_posting(writer, posting)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn ledger_to_string(&self, ledger: &Ledger) -> String {
+        let mut res = Vec::new();
+        self.write_ledger(&mut res, ledger).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+
+    pub fn transaction_to_string(&self, transaction: &Transaction) -> String {
+        let mut res = Vec::new();
+        self.write_transaction(&mut res, transaction).unwrap();
+        String::from_utf8(res).unwrap()
+    }
+
+    fn write_posting<W>(&self, writer: &mut W, posting: &Posting) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "  ")?;
+        if let Some(flag) = flag(posting.status) {
+            write!(writer, "{} ", flag)?;
+        }
+        write!(
+            writer,
+            "{}  {}",
+            account_name(&posting.account),
+            self.amount(&posting.amount)
+        )?;
+        if let Some(ref comment) = posting.comment {
+            write!(writer, " ; {}", comment.replace('\n', " "))?;
+        }
+        writeln!(writer)
+    }
+
+    fn amount(&self, amount: &Amount) -> String {
+        format!(
+            "{} {}",
+            amount.quantity,
+            self.currency(&amount.commodity.name)
+        )
+    }
+
+    fn currency(&self, commodity_name: &str) -> String {
+        match self.currencies.get(commodity_name) {
+            Some(currency) => currency.clone(),
+            None => commodity_name.to_uppercase(),
+        }
+    }
+}
+
+fn flag(status: Option<TransactionStatus>) -> Option<&'static str> {
+    match status {
+        Some(TransactionStatus::Cleared) => Some("*"),
+        Some(TransactionStatus::Pending) => Some("!"),
+        None => None,
+    }
+}
+
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Capitalizes every component of the account name
+/// and replaces characters not allowed by Beancount with `-`.
+fn account_name(account: &str) -> String {
+    account
+        .split(':')
+        .map(|component| {
+            let mut chars = component.trim().chars();
+            let first = chars.next().map(|c| c.to_uppercase().to_string());
+            first
+                .into_iter()
+                .chain(chars.map(|c| {
+                    if c.is_alphanumeric() || c == '-' {
+                        c.to_string()
+                    } else {
+                        "-".to_string()
+                    }
+                }))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beancount() {
+        let ledger: Ledger = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+; monthly salary
+2020-01-15 * Employer | Salary "January"
+  assets:bank:checking account  $100
+  income:salary
+
+2020-01-20 Food
+  Expenses:Food  12.50 PLN  ; lunch
+  ! Assets:Cash
+"#
+        .parse()
+        .unwrap();
+
+        let serializer = BeancountSerializer::new().with_currency("$", "USD");
+        assert_eq!(
+            serializer.ledger_to_string(&ledger),
+            r#"2020-01-01 price EUR 4.00 PLN
+
+; monthly salary
+2020-01-15 * "Employer" "Salary \"January\""
+  Assets:Bank:Checking-account  100 USD
+  Income:Salary  -100 USD
+
+2020-01-20 txn "Food"
+  Expenses:Food  12.50 PLN ; lunch
+  ! Assets:Cash  -12.50 PLN
+"#
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::account_balance::AccountBalance;
+use crate::balance::{Balance, DateMode};
+use crate::{Amount, Ledger};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+/// Budgeted and actual amount of a single account in a single commodity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetLine {
+    pub account: String,
+    pub budgeted: Amount,
+    pub actual: Amount,
+
+    /// `budgeted - actual`.
+    pub remaining: Amount,
+}
+
+/// Compares postings of `actual` and `budget` dated within `[start, end]`.
+///
+/// Returns a line for every account starting with one of `prefixes` and every commodity
+/// present in either ledger, sorted by account and commodity name.
+pub fn budget_report(
+    actual: &Ledger,
+    budget: &Ledger,
+    period: (NaiveDate, NaiveDate),
+    prefixes: &[&str],
+) -> Vec<BudgetLine> {
+    let (start, end) = period;
+    let actual_balance = Balance::in_range(actual, start, end, DateMode::Posting);
+    let budget_balance = Balance::in_range(budget, start, end, DateMode::Posting);
+
+    let is_included = |account: &str| prefixes.iter().any(|prefix| account.starts_with(prefix));
+    let empty = AccountBalance::new();
+
+    let mut accounts: BTreeMap<&str, (&AccountBalance, &AccountBalance)> = BTreeMap::new();
+    for (account, balance) in &budget_balance.account_balances {
+        accounts.entry(account).or_insert((&empty, &empty)).0 = balance;
+    }
+    for (account, balance) in &actual_balance.account_balances {
+        accounts.entry(account).or_insert((&empty, &empty)).1 = balance;
+    }
+
+    let mut lines = Vec::new();
+    for (account, (budgeted, actual)) in accounts {
+        if !is_included(account) {
+            continue;
+        }
+
+        let mut commodities: BTreeMap<&str, &Amount> = budgeted.amounts_by_commodity();
+        commodities.extend(actual.amounts_by_commodity());
+
+        for (commodity_name, amount) in commodities {
+            let quantity_of = |balance: &AccountBalance| {
+                balance
+                    .amounts
+                    .get(commodity_name)
+                    .map_or(Decimal::ZERO, |amount| amount.quantity)
+            };
+            let amount_of = |quantity: Decimal| Amount {
+                quantity,
+                commodity: amount.commodity.clone(),
+            };
+
+            let budgeted = quantity_of(budgeted);
+            let actual = quantity_of(actual);
+            lines.push(BudgetLine {
+                account: account.to_string(),
+                budgeted: amount_of(budgeted),
+                actual: amount_of(actual),
+                remaining: amount_of(budgeted - actual),
+            });
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::date;
+
+    #[test]
+    fn test_budget_report() {
+        let actual: Ledger = r#"
+2020-01-10 Food
+  Expenses:Food  $120
+  Assets:Bank
+
+2020-01-15 Cinema
+  Expenses:Fun  $30
+  Assets:Bank
+
+2020-02-10 Food
+  Expenses:Food  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let budget: Ledger = r#"
+2020-01-01 Budget
+  Expenses:Food  $100
+  Expenses:Rent  $500
+  Assets:Budget
+"#
+        .parse()
+        .unwrap();
+
+        let lines: Vec<_> = budget_report(
+            &actual,
+            &budget,
+            (date(2020, 1, 1), date(2020, 1, 31)),
+            &["Expenses"],
+        )
+        .into_iter()
+        .map(|line| {
+            (
+                line.account,
+                line.budgeted.to_string(),
+                line.actual.to_string(),
+                line.remaining.to_string(),
+            )
+        })
+        .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    "Expenses:Food".to_string(),
+                    "$100".to_string(),
+                    "$120".to_string(),
+                    "$-20".to_string()
+                ),
+                (
+                    "Expenses:Fun".to_string(),
+                    "$0".to_string(),
+                    "$30".to_string(),
+                    "$-30".to_string()
+                ),
+                (
+                    "Expenses:Rent".to_string(),
+                    "$500".to_string(),
+                    "$0".to_string(),
+                    "$500".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,10 +1,14 @@
+//! Balancing of `ledger_parser` transactions: filling in omitted amounts
+//! and checking balance assertions, as done by `TryFrom<ledger_parser::Ledger> for Ledger`.
+
 use crate::account_balance::AccountBalance;
+use crate::amount_ext::AmountExt;
 use crate::balance::Balance;
 use crate::simplified_ledger::Error;
 use chrono::NaiveDate;
 use ledger_parser::{
-    Amount, Commodity, CommodityPosition, CommodityPrice, Posting, PostingAmount, PostingMetadata,
-    Reality, Transaction,
+    Amount, Commodity, CommodityPosition, CommodityPrice, Posting, PostingAmount, Price, Reality,
+    Transaction,
 };
 use ledger_parser::{Balance::Amount as BalanceAmount, Balance::Zero as BalanceZero};
 use rust_decimal::Decimal;
@@ -14,32 +18,134 @@ use rust_decimal::Decimal;
 ///
 /// "Balance assertions" are postings with both amount and balance provided. The calculated
 /// amount using the balance must match the given amount.
+///
+/// On success every posting has an `amount`. Prices inferred from postings
+/// exchanging two commodities are appended to `commodity_prices`.
 pub fn calculate_amounts_from_balances(
     transactions: &mut Vec<Transaction>,
     commodity_prices: &mut Vec<CommodityPrice>,
+) -> Result<(), Error> {
+    calculate_amounts_from_balances_with_tolerance(transactions, commodity_prices, Decimal::ZERO)
+}
+
+/// Like `calculate_amounts_from_balances`, but a transaction is also accepted
+/// if every residual amount is within `tolerance` (e.g. rounding errors of imported data).
+///
+/// The residual is absorbed into the last posting with an amount in the same commodity
+/// (without a price), so the transaction balances exactly.
+pub fn calculate_amounts_from_balances_with_tolerance(
+    transactions: &mut Vec<Transaction>,
+    commodity_prices: &mut Vec<CommodityPrice>,
+    tolerance: Decimal,
+) -> Result<(), Error> {
+    calculate_amounts(transactions, commodity_prices, tolerance, false)
+}
+
+/// Like `calculate_amounts_from_balances_with_tolerance`. With `keep_balances`, the `balance`s
+/// of postings are kept (e.g. to write them back as balance assertions).
+pub(crate) fn calculate_amounts(
+    transactions: &mut Vec<Transaction>,
+    commodity_prices: &mut Vec<CommodityPrice>,
+    tolerance: Decimal,
+    keep_balances: bool,
 ) -> Result<(), Error> {
     let mut running_balance = Some(Balance::new());
 
     for transaction in transactions {
-        calculate_transaction_amounts(transaction, commodity_prices, &mut running_balance)?;
+        calculate_transaction_amounts(
+            transaction,
+            commodity_prices,
+            &mut running_balance,
+            tolerance,
+            keep_balances,
+        )?;
     }
 
     Ok(())
 }
 
+/// Like `calculate_amounts_from_balances`, but doesn't stop at the first error.
+///
+/// Failing transactions are removed from `transactions` and don't affect the running balance
+/// used for the following ones. Returns errors of all of them, in order.
+pub fn calculate_amounts_from_balances_all(
+    transactions: &mut Vec<Transaction>,
+    commodity_prices: &mut Vec<CommodityPrice>,
+) -> Vec<Error> {
+    calculate_amounts_all(transactions, commodity_prices, false)
+}
+
+/// Like `calculate_amounts_from_balances_all`. With `keep_balances`, the `balance`s
+/// of postings are kept.
+pub(crate) fn calculate_amounts_all(
+    transactions: &mut Vec<Transaction>,
+    commodity_prices: &mut Vec<CommodityPrice>,
+    keep_balances: bool,
+) -> Vec<Error> {
+    let mut running_balance = Some(Balance::new());
+    let mut errors = Vec::new();
+
+    transactions.retain_mut(|transaction| {
+        let touched_accounts: Vec<(String, Option<AccountBalance>)> = transaction
+            .postings
+            .iter()
+            .map(|posting| {
+                let account_balance = running_balance
+                    .as_ref()
+                    .and_then(|b| b.account_balances.get(&posting.account).cloned());
+                (posting.account.clone(), account_balance)
+            })
+            .collect();
+        let commodity_prices_len = commodity_prices.len();
+
+        match calculate_transaction_amounts(
+            transaction,
+            commodity_prices,
+            &mut running_balance,
+            Decimal::ZERO,
+            keep_balances,
+        ) {
+            Ok(()) => true,
+            Err(e) => {
+                // revert changes made by the failing transaction
+                let running_balance = running_balance.as_mut().unwrap();
+                for (account, account_balance) in touched_accounts.into_iter().rev() {
+                    match account_balance {
+                        Some(b) => running_balance.account_balances.insert(account, b),
+                        None => running_balance.account_balances.remove(&account),
+                    };
+                }
+                commodity_prices.truncate(commodity_prices_len);
+                errors.push(e);
+                false
+            }
+        }
+    });
+
+    errors
+}
+
 /// Fails if any transactions are unbalanced, or if an unbalanced virtual posting
 /// (account name in `()`) has no amount.
 ///
 /// Ignores `balance`s. Fails if they are necessary to fill in any omitted `amount`s.
 pub fn calculate_omitted_amounts(transaction: &mut Transaction) -> Result<(), Error> {
     let mut commodity_prices = Vec::new();
-    calculate_transaction_amounts(transaction, &mut commodity_prices, &mut None)
+    calculate_transaction_amounts(
+        transaction,
+        &mut commodity_prices,
+        &mut None,
+        Decimal::ZERO,
+        false,
+    )
 }
 
 fn calculate_transaction_amounts(
     transaction: &mut Transaction,
     commodity_prices: &mut Vec<CommodityPrice>,
     running_balance: &mut Option<Balance>,
+    tolerance: Decimal,
+    keep_balances: bool,
 ) -> Result<(), Error> {
     let original_transaction = transaction.clone();
 
@@ -47,14 +153,23 @@ fn calculate_transaction_amounts(
     let mut virtual_transaction_balance = AccountBalance::new();
 
     // For postings with amounts, add those amounts to the transaction and running balances.
+    // Postings with an attached price (`@`, `@@` or `{}`) are balanced by their priced value.
     for posting in &transaction.postings {
-        if let Some(PostingAmount { ref amount, .. }) = posting.amount {
+        if let Some(ref posting_amount) = posting.amount {
+            let amount = &posting_amount.amount;
+            let value = get_posting_value(posting_amount);
             match posting.reality {
-                Reality::Real => real_transaction_balance += amount,
-                Reality::BalancedVirtual => virtual_transaction_balance += amount,
+                Reality::Real => real_transaction_balance += &value,
+                Reality::BalancedVirtual => virtual_transaction_balance += &value,
                 Reality::UnbalancedVirtual => (),
             }
 
+            if let Some(commodity_price) =
+                get_commodity_price_from_posting(transaction.date, posting_amount)
+            {
+                commodity_prices.push(commodity_price);
+            }
+
             if let Some(running_balance) = running_balance {
                 running_balance.add_amount(&posting.account, amount);
             }
@@ -72,6 +187,10 @@ fn calculate_transaction_amounts(
                     &mut real_transaction_balance,
                     &mut virtual_transaction_balance,
                 )?;
+
+                if !keep_balances {
+                    posting.balance = None;
+                }
             }
         }
     }
@@ -81,7 +200,7 @@ fn calculate_transaction_amounts(
         posting
             .amount
             .as_ref()
-            .map_or(true, |amt| !amt.amount.commodity.name.is_empty())
+            .is_none_or(|amt| !amt.amount.commodity.name.is_empty())
     });
 
     // For any postings still without amounts, calculate the amount from the transaction balance.
@@ -117,20 +236,37 @@ fn calculate_transaction_amounts(
     }
 
     // Check that all real and virtual postings now balance.
-    if !real_transaction_balance.is_zero()
-        && !handle_commodity_exchange(
-            transaction.date,
-            &real_transaction_balance,
-            commodity_prices,
-        )
-        || !virtual_transaction_balance.is_zero()
-            && !handle_commodity_exchange(
-                transaction.date,
-                &virtual_transaction_balance,
-                commodity_prices,
-            )
-    {
-        return Err(Error::UnbalancedTransaction(transaction.clone().into()));
+    for (balance, reality) in [
+        (real_transaction_balance, Reality::Real),
+        (virtual_transaction_balance, Reality::BalancedVirtual),
+    ] {
+        if balance.is_zero()
+            || handle_commodity_exchange(transaction.date, &balance, commodity_prices)
+        {
+            continue;
+        }
+
+        let mut residual = balance;
+        residual.amounts.retain(|_, amount| !amount.is_zero());
+        if residual
+            .amounts
+            .values()
+            .any(|amount| amount.quantity.abs() > tolerance)
+        {
+            return Err(Error::UnbalancedTransaction(
+                transaction.clone().into(),
+                residual,
+            ));
+        }
+
+        for amount in residual.amounts.values() {
+            if !absorb_residual(&mut new_postings, reality, amount, running_balance) {
+                return Err(Error::UnbalancedTransaction(
+                    transaction.clone().into(),
+                    residual,
+                ));
+            }
+        }
     }
 
     transaction.postings = new_postings;
@@ -138,6 +274,82 @@ fn calculate_transaction_amounts(
     Ok(())
 }
 
+// Subtracts the residual from the last posting of the same reality and commodity.
+// Returns false if there is no such posting without a price.
+fn absorb_residual(
+    postings: &mut [Posting],
+    reality: Reality,
+    residual: &Amount,
+    running_balance: &mut Option<Balance>,
+) -> bool {
+    let posting = postings.iter_mut().rev().find(|posting| {
+        posting.reality == reality
+            && posting.amount.as_ref().is_some_and(|posting_amount| {
+                posting_amount.price.is_none()
+                    && posting_amount.lot_price.is_none()
+                    && posting_amount.amount.same_commodity(residual)
+            })
+    });
+
+    if let Some(posting) = posting {
+        posting.amount.as_mut().unwrap().amount.quantity -= residual.quantity;
+        if let Some(running_balance) = running_balance {
+            running_balance.add_amount(&posting.account, &residual.negated());
+        }
+        true
+    } else {
+        false
+    }
+}
+
+// Value of the posting amount used for balancing the transaction.
+// Explicit price takes precedence over lot price.
+fn get_posting_value(posting_amount: &PostingAmount) -> Amount {
+    let amount = &posting_amount.amount;
+    match posting_amount
+        .price
+        .as_ref()
+        .or(posting_amount.lot_price.as_ref())
+    {
+        Some(Price::Unit(unit_price)) => Amount {
+            quantity: amount.quantity * unit_price.quantity,
+            commodity: unit_price.commodity.clone(),
+        },
+        Some(Price::Total(total_price)) => Amount {
+            quantity: if amount.quantity.is_sign_negative() {
+                -total_price.quantity.abs()
+            } else {
+                total_price.quantity.abs()
+            },
+            commodity: total_price.commodity.clone(),
+        },
+        None => amount.clone(),
+    }
+}
+
+// Commodity price implied by a posting with an attached `@` / `@@` price.
+// A lot price is the historical cost, so it only balances the transaction.
+fn get_commodity_price_from_posting(
+    transaction_date: NaiveDate,
+    posting_amount: &PostingAmount,
+) -> Option<CommodityPrice> {
+    posting_amount.price.as_ref()?;
+    let amount = &posting_amount.amount;
+    let value = get_posting_value(posting_amount);
+    if value.same_commodity(amount) || amount.is_zero() {
+        return None;
+    }
+
+    Some(CommodityPrice {
+        datetime: transaction_date.and_hms_opt(0, 0, 0).unwrap(),
+        commodity_name: amount.commodity.name.clone(),
+        amount: Amount {
+            quantity: value.quantity / amount.quantity,
+            commodity: value.commodity,
+        },
+    })
+}
+
 // Handle the case where there are exactly two commodities that are non-zero, by
 // creating a commodity price for this date that makes the transaction balance.
 fn handle_commodity_exchange(
@@ -158,7 +370,9 @@ fn get_commodity_price_from_balance(
     balance: &AccountBalance,
 ) -> Option<CommodityPrice> {
     if balance.amounts.len() == 2 {
-        let amounts: Vec<_> = balance.amounts.iter().map(|f| f.1).collect();
+        // sorted, so the same transaction always gives the same price
+        let mut amounts: Vec<_> = balance.amounts.values().collect();
+        amounts.sort_by_key(|amount| &amount.commodity.name);
 
         if amounts[0].quantity != Decimal::new(0, 0) && amounts[1].quantity != Decimal::new(0, 0) {
             return Some(CommodityPrice {
@@ -279,8 +493,6 @@ fn calculate_posting_amounts_from_balances(
                 price: None,
             });
         }
-
-        posting.balance = None;
     }
 
     Ok(())
@@ -293,7 +505,9 @@ fn calculate_omitted_amounts_for_posting(
     virtual_transaction_balance: &mut AccountBalance,
 ) -> Result<Vec<Posting>, Error> {
     // Posting has no amount or balance, figure out what value (or values, if multi-commodity)
-    // to insert to make the transaction balance
+    // to insert to make the transaction balance.
+    // A posting balancing several commodities is split into one posting per commodity,
+    // each keeping the account, status, comment and metadata of the original.
     let transaction_balance = match posting.reality {
         Reality::Real => real_transaction_balance,
         Reality::BalancedVirtual => virtual_transaction_balance,
@@ -311,21 +525,14 @@ fn calculate_omitted_amounts_for_posting(
     let new_postings = sorted_amounts
         .iter()
         .map(|amount| {
-            let amount = Amount {
-                commodity: amount.commodity.clone(),
-                quantity: -amount.quantity,
-            };
+            let amount = amount.negated();
 
             Posting {
                 account: posting.account.clone(),
                 reality: posting.reality,
                 status: posting.status,
                 comment: posting.comment.clone(),
-                metadata: PostingMetadata {
-                    date: None,
-                    effective_date: None,
-                    tags: vec![],
-                },
+                metadata: posting.metadata.clone(),
                 amount: Some(PostingAmount {
                     amount,
                     lot_price: None,
@@ -360,6 +567,18 @@ mod tests {
             .unwrap()
     }
 
+    fn dollars(quantity: Decimal) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        balance += &Amount {
+            quantity,
+            commodity: Commodity {
+                name: "$".to_string(),
+                position: CommodityPosition::Left,
+            },
+        };
+        balance
+    }
+
     fn parse_transactions(input: &str) -> Vec<Transaction> {
         input
             .parse::<Ledger>()
@@ -443,7 +662,8 @@ mod tests {
         assert_eq!(
             calculate_omitted_amounts(&mut transaction),
             Err(Error::UnbalancedTransaction(
-                original_transaction.clone().into()
+                original_transaction.clone().into(),
+                dollars(Decimal::new(-1, 2))
             ))
         );
         assert_eq!(transaction, original_transaction);
@@ -531,7 +751,8 @@ mod tests {
         assert_eq!(
             calculate_omitted_amounts(&mut transaction),
             Err(Error::UnbalancedTransaction(
-                original_transaction.clone().into()
+                original_transaction.clone().into(),
+                dollars(Decimal::new(-5, 2))
             ))
         );
         assert_eq!(transaction, original_transaction);
@@ -553,7 +774,8 @@ mod tests {
         assert_eq!(
             calculate_omitted_amounts(&mut transaction),
             Err(Error::UnbalancedTransaction(
-                original_transaction.clone().into()
+                original_transaction.clone().into(),
+                dollars(Decimal::new(340, 2))
             ))
         );
         assert_eq!(transaction, original_transaction);
@@ -596,6 +818,139 @@ mod tests {
         assert_eq!(transaction, original_transaction);
     }
 
+    #[test]
+    fn test_calculate_omitted_amounts_priced_postings() {
+        let mut transaction = parse_transaction(
+            r#"
+2020-02-01 Buy
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:cash             100 EUR @@ $110
+  assets:bank:checking   $-150
+"#,
+        );
+        let original_transaction = transaction.clone();
+        assert_eq!(calculate_omitted_amounts(&mut transaction), Ok(()));
+        assert_eq!(transaction, original_transaction);
+
+        let mut transaction = parse_transaction(
+            r#"
+2020-02-01 Buy
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:cash             100 EUR @@ $110
+  assets:bank:checking   $-151
+"#,
+        );
+        assert!(calculate_omitted_amounts(&mut transaction).is_err());
+    }
+
+    #[test]
+    fn test_calculate_omitted_amounts_priced_posting() {
+        let mut transaction = parse_transaction(
+            r#"
+2020-02-01 Buy ADA
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:bank:checking
+"#,
+        );
+        let expected_transaction = parse_transaction(
+            r#"
+2020-02-01 Buy ADA
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:bank:checking   $-40.00
+"#,
+        );
+        assert_eq!(calculate_omitted_amounts(&mut transaction), Ok(()));
+        assert_eq!(transaction, expected_transaction);
+
+        let mut transaction = parse_transaction(
+            r#"
+2020-02-03 Sell ADA
+  assets:cc:ada          -1000 ADA @@ $30
+  assets:bank:checking
+"#,
+        );
+        let expected_transaction = parse_transaction(
+            r#"
+2020-02-03 Sell ADA
+  assets:cc:ada          -1000 ADA @@ $30
+  assets:bank:checking   $30
+"#,
+        );
+        assert_eq!(calculate_omitted_amounts(&mut transaction), Ok(()));
+        assert_eq!(transaction, expected_transaction);
+    }
+
+    #[test]
+    fn test_calculate_amounts_from_balances_inferred_prices() {
+        let mut transactions = parse_transactions(
+            r#"
+2020-02-01 Buy
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:cash            -100 EUR @@ $110
+  assets:bank:checking   $70
+
+2020-03-01 Sell
+  assets:cc:ada          -1000 ADA {$0.02} @ $0.05
+  assets:cc:ada          -1000 ADA {$0.02}
+  assets:bank:checking   $70
+"#,
+        );
+        let mut commodity_prices = Vec::new();
+        assert_eq!(
+            calculate_amounts_from_balances(&mut transactions, &mut commodity_prices),
+            Ok(())
+        );
+        let prices: Vec<_> = commodity_prices
+            .iter()
+            .map(|price| (price.commodity_name.as_str(), price.amount.to_string()))
+            .collect();
+        assert_eq!(
+            prices,
+            vec![
+                ("ADA", "$0.02".to_string()),
+                ("EUR", "$1.10".to_string()),
+                // lot prices are not market prices
+                ("ADA", "$0.05".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calculate_amounts_from_balances_all() {
+        let mut transactions = parse_transactions(
+            r#"
+2020-01-01 Opening
+  Assets:Bank  $100
+  Equity:Opening
+
+2020-01-02 Unbalanced
+  Assets:Bank  $10
+  Expenses:Food  $5
+
+2020-01-03 Assertion
+  Assets:Bank  $-20 = $80
+  Expenses:Food
+
+2020-01-04 Unbalanced again
+  Assets:Bank  $1
+  Expenses:Food  $1
+"#,
+        );
+        let mut commodity_prices = Vec::new();
+
+        let errors = calculate_amounts_from_balances_all(&mut transactions, &mut commodity_prices);
+        let descriptions: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                Error::UnbalancedTransaction(t, _) => t.description.as_str(),
+                e => panic!("unexpected error: {}", e),
+            })
+            .collect();
+        assert_eq!(descriptions, vec!["Unbalanced", "Unbalanced again"]);
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].description, "Assertion");
+    }
+
     #[test]
     fn test_calculate_amounts_from_balances_no_change() {
         let mut transactions = parse_transactions(
@@ -799,7 +1154,82 @@ mod tests {
         );
         assert_eq!(
             calculate_amounts_from_balances(&mut transactions, &mut Vec::new()),
-            Err(Error::UnbalancedTransaction(error_transaction.into()))
+            Err(Error::UnbalancedTransaction(
+                error_transaction.into(),
+                dollars(Decimal::new(241, 2))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_calculate_amounts_from_balances_with_tolerance() {
+        let input = r#"
+2018-10-01 Marek Ogarek
+  TEST:ABC   $1.20
+  TEST:DEF  $-1.21
+  TEST:GHI   0.10 EUR
+  TEST:GHI  -0.10 EUR
+
+2018-10-02 Marek Ogarek
+  TEST:DEF         = $0.00
+  TEST:ABC
+"#;
+        let mut transactions = parse_transactions(input);
+        assert_eq!(
+            calculate_amounts_from_balances_with_tolerance(
+                &mut transactions,
+                &mut Vec::new(),
+                Decimal::new(1, 3)
+            ),
+            Err(Error::UnbalancedTransaction(
+                parse_transaction(input).into(),
+                dollars(Decimal::new(-1, 2))
+            ))
+        );
+
+        let mut transactions = parse_transactions(input);
+        let expected_transactions = parse_transactions(
+            r#"
+2018-10-01 Marek Ogarek
+  TEST:ABC   $1.20
+  TEST:DEF  $-1.20
+  TEST:GHI   0.10 EUR
+  TEST:GHI  -0.10 EUR
+
+2018-10-02 Marek Ogarek
+  TEST:DEF   $1.20
+  TEST:ABC  $-1.20
+"#,
+        );
+        assert_eq!(
+            calculate_amounts_from_balances_with_tolerance(
+                &mut transactions,
+                &mut Vec::new(),
+                Decimal::new(1, 2)
+            ),
+            Ok(())
+        );
+        assert_eq!(transactions, expected_transactions);
+    }
+
+    #[test]
+    fn test_calculate_amounts_with_tolerance_error_residual_without_unpriced_posting() {
+        let input = r#"
+2018-10-01 Marek Ogarek
+  TEST:ABC   1 AAA @ $10.005
+  TEST:DEF  -1 BBB @ $10.00
+"#;
+        let mut transactions = parse_transactions(input);
+        assert_eq!(
+            calculate_amounts_from_balances_with_tolerance(
+                &mut transactions,
+                &mut Vec::new(),
+                Decimal::new(1, 2)
+            ),
+            Err(Error::UnbalancedTransaction(
+                parse_transaction(input).into(),
+                dollars(Decimal::new(5, 3))
+            ))
         );
     }
 
@@ -912,7 +1342,10 @@ mod tests {
         );
         assert_eq!(
             calculate_amounts_from_balances(&mut transactions, &mut Vec::new()),
-            Err(Error::UnbalancedTransaction(error_transaction.into()))
+            Err(Error::UnbalancedTransaction(
+                error_transaction.into(),
+                dollars(Decimal::new(1, 2))
+            ))
         );
     }
 
@@ -975,7 +1408,10 @@ mod tests {
         );
         assert_eq!(
             calculate_amounts_from_balances(&mut transactions, &mut Vec::new()),
-            Err(Error::UnbalancedTransaction(error_transaction.into()))
+            Err(Error::UnbalancedTransaction(
+                error_transaction.into(),
+                dollars(Decimal::new(340, 2))
+            ))
         );
     }
 
@@ -0,0 +1,771 @@
+use crate::prices::{Prices, PricesError};
+use crate::{Amount, Ledger, Posting, Price, Reality, Transaction};
+use chrono::NaiveDate;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::{HashMap, VecDeque};
+
+/// Source of cost basis of disposed commodities.
+pub trait CostBasis {
+    /// Cost, in the main commodity, of `quantity` units of `commodity`
+    /// disposed from `account` on `date`.
+    fn cost_basis_of(
+        &self,
+        account: &str,
+        commodity: &str,
+        quantity: Decimal,
+        date: NaiveDate,
+    ) -> Decimal;
+
+    /// Cost of the disposal in posting `posting_index` of `transaction`, which is
+    /// at `transaction_index` in the ledger. Defaults to `cost_basis_of` for
+    /// the posting's account, commodity and quantity on the transaction date.
+    fn disposal_cost_basis(
+        &self,
+        _transaction_index: usize,
+        posting_index: usize,
+        transaction: &Transaction,
+    ) -> Decimal {
+        let posting = &transaction.postings[posting_index];
+        self.cost_basis_of(
+            &posting.account,
+            &posting.amount.commodity.name,
+            -posting.amount.quantity,
+            transaction.date,
+        )
+    }
+}
+
+/// Which lots are matched with a disposal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LotMethod {
+    /// First in, first out.
+    #[default]
+    Fifo,
+    /// Last in, first out.
+    Lifo,
+    /// All lots are merged and valued at their average cost.
+    Average,
+}
+
+/// Commodity acquired at once, with cost in the main commodity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lot {
+    pub date: NaiveDate,
+    pub quantity: Decimal,
+    pub unit_cost: Decimal,
+}
+
+#[derive(Debug, Clone)]
+enum LotEvent {
+    Acquisition(Lot),
+    Disposal {
+        date: NaiveDate,
+        transaction_index: usize,
+        posting_index: usize,
+        quantity: Decimal,
+        cost: Decimal,
+    },
+}
+
+/// Lot acquired without a known price, tracked with zero cost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnpricedLot {
+    pub account: String,
+    pub commodity: String,
+    pub lot: Lot,
+}
+
+/// Tracks acquisition lots per `(account, commodity)` to find the cost basis
+/// of disposed commodities.
+///
+/// Every posting with positive quantity of a commodity other than the main one
+/// is an acquisition, valued with its `{}` / `{{}}` lot price or its `@` / `@@` price
+/// (converted with `prices` if it's not in the main commodity). Acquisitions
+/// without a posting price are valued with `prices` on their date, and if there
+/// is none either, they are tracked with zero cost and reported by `unpriced_lots`. Every posting with negative quantity is a disposal.
+/// Transfers between accounts are therefore treated as a disposal and a new
+/// acquisition.
+#[derive(Debug, Clone)]
+pub struct LotTracker {
+    method: LotMethod,
+    events: HashMap<(String, String), Vec<LotEvent>>,
+    unpriced_lots: Vec<UnpricedLot>,
+}
+
+impl LotTracker {
+    pub fn new(
+        ledger: &Ledger,
+        main_commodity: &str,
+        method: LotMethod,
+        prices: &Prices,
+    ) -> LotTracker {
+        let mut tracker = LotTracker {
+            method,
+            events: HashMap::new(),
+            unpriced_lots: Vec::new(),
+        };
+        let mut lots: HashMap<(String, String), VecDeque<Lot>> = HashMap::new();
+
+        let mut transactions: Vec<_> = ledger.transactions.iter().enumerate().collect();
+        transactions.sort_by_key(|(_, transaction)| transaction.date);
+
+        for (transaction_index, transaction) in transactions {
+            for (posting_index, posting) in transaction.postings.iter().enumerate() {
+                let commodity_name = &posting.amount.commodity.name;
+                let quantity = posting.amount.quantity;
+                if commodity_name == main_commodity || quantity.is_zero() {
+                    continue;
+                }
+
+                let key = (posting.account.clone(), commodity_name.clone());
+                let account_lots = lots.entry(key.clone()).or_default();
+                let event = if quantity > Decimal::ZERO {
+                    let unit_cost =
+                        posting_unit_cost(posting, main_commodity, transaction.date, prices);
+                    let lot = Lot {
+                        date: transaction.date,
+                        quantity,
+                        unit_cost: unit_cost.unwrap_or_default(),
+                    };
+                    if unit_cost.is_none() {
+                        tracker.unpriced_lots.push(UnpricedLot {
+                            account: key.0.clone(),
+                            commodity: key.1.clone(),
+                            lot: lot.clone(),
+                        });
+                    }
+                    add_lot(account_lots, lot.clone(), method);
+                    LotEvent::Acquisition(lot)
+                } else {
+                    LotEvent::Disposal {
+                        date: transaction.date,
+                        transaction_index,
+                        posting_index,
+                        quantity: -quantity,
+                        cost: consume_lots(account_lots, -quantity, method),
+                    }
+                };
+                tracker.events.entry(key).or_default().push(event);
+            }
+        }
+
+        tracker
+    }
+
+    /// Acquisitions that had no price on their date.
+    pub fn unpriced_lots(&self) -> &[UnpricedLot] {
+        &self.unpriced_lots
+    }
+
+    /// Lots held in `account` after all transactions on or before `date`.
+    pub fn lots_at(&self, account: &str, commodity: &str, date: NaiveDate) -> Vec<Lot> {
+        let mut lots = VecDeque::new();
+        let events = self
+            .events
+            .get(&(account.to_string(), commodity.to_string()));
+        for event in events.into_iter().flatten() {
+            match event {
+                LotEvent::Acquisition(lot) if lot.date <= date => {
+                    add_lot(&mut lots, lot.clone(), self.method)
+                }
+                LotEvent::Disposal {
+                    date: disposal_date,
+                    quantity,
+                    ..
+                } if *disposal_date <= date => {
+                    consume_lots(&mut lots, *quantity, self.method);
+                }
+                _ => break,
+            }
+        }
+        lots.into()
+    }
+}
+
+impl CostBasis for LotTracker {
+    /// Matches `quantity` with lots held after all transactions on `date`.
+    /// Quantity not covered by any lot has zero cost.
+    fn cost_basis_of(
+        &self,
+        account: &str,
+        commodity: &str,
+        quantity: Decimal,
+        date: NaiveDate,
+    ) -> Decimal {
+        let mut lots = self.lots_at(account, commodity, date).into();
+        consume_lots(&mut lots, quantity, self.method)
+    }
+
+    /// Returns cost of the disposal from the ledger the tracker was created with.
+    fn disposal_cost_basis(
+        &self,
+        transaction_index: usize,
+        posting_index: usize,
+        transaction: &Transaction,
+    ) -> Decimal {
+        let posting = &transaction.postings[posting_index];
+        let events = self.events.get(&(
+            posting.account.clone(),
+            posting.amount.commodity.name.clone(),
+        ));
+        events
+            .into_iter()
+            .flatten()
+            .find_map(|event| match event {
+                LotEvent::Disposal {
+                    transaction_index: disposal_transaction_index,
+                    posting_index: disposal_posting_index,
+                    cost,
+                    ..
+                } if *disposal_transaction_index == transaction_index
+                    && *disposal_posting_index == posting_index =>
+                {
+                    Some(*cost)
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Unit cost of an acquisition in the main commodity, from its `{}` / `@` price
+// or, if it has none, from the market price.
+fn posting_unit_cost(
+    posting: &Posting,
+    main_commodity: &str,
+    date: NaiveDate,
+    prices: &Prices,
+) -> Option<Decimal> {
+    let quantity = posting.amount.quantity;
+    let Some(price) = posting.lot_price.as_ref().or(posting.price.as_ref()) else {
+        return prices
+            .get_rate(&posting.amount.commodity.name, main_commodity, date)
+            .ok();
+    };
+    let unit_price = match price {
+        Price::Unit(unit_price) => unit_price.quantity,
+        Price::Total(total_price) => total_price.quantity.abs() / quantity.abs(),
+    };
+    let price_commodity = match price {
+        Price::Unit(amount) | Price::Total(amount) => &amount.commodity.name,
+    };
+    if price_commodity == main_commodity {
+        Some(unit_price)
+    } else {
+        prices
+            .convert(unit_price, price_commodity, main_commodity, date)
+            .ok()
+    }
+}
+
+fn add_lot(lots: &mut VecDeque<Lot>, lot: Lot, method: LotMethod) {
+    match (method, lots.front_mut()) {
+        (LotMethod::Average, Some(average)) => {
+            let total_cost = average.quantity * average.unit_cost + lot.quantity * lot.unit_cost;
+            average.quantity += lot.quantity;
+            average.unit_cost = total_cost / average.quantity;
+            average.date = lot.date;
+        }
+        _ => lots.push_back(lot),
+    }
+}
+
+// Removes `quantity` from lots and returns its cost.
+fn consume_lots(lots: &mut VecDeque<Lot>, mut quantity: Decimal, method: LotMethod) -> Decimal {
+    let mut cost = Decimal::ZERO;
+    while quantity > Decimal::ZERO {
+        let lot = match method {
+            LotMethod::Fifo | LotMethod::Average => lots.front_mut(),
+            LotMethod::Lifo => lots.back_mut(),
+        };
+        let Some(lot) = lot else {
+            break;
+        };
+
+        let used = quantity.min(lot.quantity);
+        cost += used * lot.unit_cost;
+        quantity -= used;
+        lot.quantity -= used;
+
+        if lot.quantity.is_zero() {
+            match method {
+                LotMethod::Fifo | LotMethod::Average => lots.pop_front(),
+                LotMethod::Lifo => lots.pop_back(),
+            };
+        }
+    }
+    cost
+}
+
+/// Adds realized capital gain (or loss) postings to `gains_account`.
+///
+/// A disposal is a posting on an asset account with negative quantity of
+/// a commodity other than `main_commodity`, in a transaction where an asset
+/// account receives `main_commodity`. The proceeds are the received amount,
+/// split between disposals in proportion to their value if there are several
+/// of them. The value is taken from the `@` / `@@` posting price or,
+/// for postings without one, from `prices`.
+///
+/// The gain is the proceeds minus the cost basis, both rounded to
+/// `main_commodity_decimal_points` (the rounding remainder of the split goes
+/// to the last disposal). Gains are posted as negative amounts (like income)
+/// and disposals are rewritten at cost (as a `{}` lot price, without the `@` price),
+/// so the transaction still balances.
+///
+/// Returns transactions that were skipped because their disposals couldn't
+/// be valued, as indices into `ledger.transactions`.
+pub fn realize_gains<F>(
+    ledger: &mut Ledger,
+    cost_basis: &dyn CostBasis,
+    gains_account: &str,
+    main_commodity: &str,
+    main_commodity_decimal_points: u32,
+    is_asset_account: &F,
+    prices: &Prices,
+) -> Vec<(usize, PricesError)>
+where
+    F: Fn(&str) -> bool,
+{
+    let round = |value: Decimal| {
+        let mut value = value.round_dp_with_strategy(
+            main_commodity_decimal_points,
+            RoundingStrategy::MidpointAwayFromZero,
+        );
+        value.rescale(main_commodity_decimal_points);
+        value
+    };
+
+    let mut skipped = Vec::new();
+    for (index, transaction) in ledger.transactions.iter_mut().enumerate() {
+        let received: Vec<_> = transaction
+            .postings
+            .iter()
+            .filter(|posting| {
+                is_asset_account(&posting.account)
+                    && posting.amount.commodity.name == main_commodity
+                    && posting.amount.quantity > Decimal::ZERO
+            })
+            .map(|posting| &posting.amount)
+            .collect();
+        let Some(main_commodity_amount) = received.first().map(|&amount| amount.clone()) else {
+            continue;
+        };
+        let proceeds: Decimal = received.iter().map(|amount| amount.quantity).sum();
+
+        let disposals: Vec<_> = transaction
+            .postings
+            .iter()
+            .enumerate()
+            .filter(|(_, posting)| {
+                is_asset_account(&posting.account)
+                    && posting.amount.commodity.name != main_commodity
+                    && posting.amount.quantity < Decimal::ZERO
+            })
+            .collect();
+        if disposals.is_empty() {
+            continue;
+        }
+        let proceeds = match split_proceeds(
+            proceeds,
+            &disposals,
+            main_commodity,
+            transaction.date,
+            prices,
+        ) {
+            Ok(proceeds) => proceeds,
+            Err(e) => {
+                skipped.push((index, e));
+                continue;
+            }
+        };
+
+        // the last disposal gets what is left after rounding the others
+        let mut remaining_proceeds = round(proceeds.iter().sum());
+        let mut realized = Vec::new();
+        for (i, ((posting_index, _), proceeds)) in disposals.iter().zip(&proceeds).enumerate() {
+            let proceeds = if i + 1 == disposals.len() {
+                remaining_proceeds
+            } else {
+                round(*proceeds)
+            };
+            remaining_proceeds -= proceeds;
+            let cost = round(cost_basis.disposal_cost_basis(index, *posting_index, transaction));
+            realized.push((*posting_index, cost, proceeds - cost));
+        }
+
+        let mut new_postings = Vec::new();
+        for (posting_index, cost, gain) in realized {
+            let posting = &mut transaction.postings[posting_index];
+            let quantity = -posting.amount.quantity;
+            let mut cost_amount = Amount {
+                quantity: cost,
+                commodity: main_commodity_amount.commodity.clone(),
+            };
+            posting.lot_price = Some(if (cost / quantity) * quantity == cost {
+                cost_amount.quantity = cost / quantity;
+                Price::Unit(cost_amount)
+            } else {
+                Price::Total(cost_amount)
+            });
+            posting.price = None;
+
+            if gain.is_zero() {
+                continue;
+            }
+
+            new_postings.push(Posting {
+                date: posting.date,
+                effective_date: posting.effective_date,
+                account: gains_account.to_string(),
+                reality: Reality::Real,
+                amount: Amount {
+                    quantity: -gain,
+                    commodity: main_commodity_amount.commodity.clone(),
+                },
+                lot_price: None,
+                price: None,
+                balance: None,
+                status: posting.status,
+                comment: Some("Auto-generated".to_string()),
+                tags: vec![],
+            });
+        }
+
+        transaction.postings.append(&mut new_postings);
+    }
+
+    skipped
+}
+
+// Splits proceeds between disposals in proportion to their value.
+fn split_proceeds(
+    proceeds: Decimal,
+    disposals: &[(usize, &Posting)],
+    main_commodity: &str,
+    date: NaiveDate,
+    prices: &Prices,
+) -> Result<Vec<Decimal>, PricesError> {
+    if disposals.len() == 1 {
+        return Ok(vec![proceeds]);
+    }
+
+    let values = disposals
+        .iter()
+        .map(|(_, posting)| {
+            let quantity = -posting.amount.quantity;
+            match &posting.price {
+                Some(Price::Unit(price)) if price.commodity.name == main_commodity => {
+                    Ok(quantity * price.quantity)
+                }
+                Some(Price::Total(price)) if price.commodity.name == main_commodity => {
+                    Ok(price.quantity.abs())
+                }
+                _ => prices.convert(
+                    quantity,
+                    &posting.amount.commodity.name,
+                    main_commodity,
+                    date,
+                ),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let total: Decimal = values.iter().sum();
+    Ok(values
+        .into_iter()
+        .map(|value| {
+            if total.is_zero() {
+                total
+            } else {
+                proceeds * value / total
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+    use std::collections::HashMap;
+
+    struct UnitCost(HashMap<&'static str, Decimal>);
+
+    impl CostBasis for UnitCost {
+        fn cost_basis_of(
+            &self,
+            _: &str,
+            commodity: &str,
+            quantity: Decimal,
+            _: NaiveDate,
+        ) -> Decimal {
+            self.0[commodity] * quantity
+        }
+    }
+
+    #[test]
+    fn test_lot_tracker() {
+        let input = r#"
+2020-01-10 Buy
+  Assets:Broker  10 AAPL @ $100
+  Assets:Bank
+
+2020-02-10 Buy
+  Assets:Broker  10 AAPL @ $200
+  Assets:Bank
+
+2020-03-10 Sell
+  Assets:Broker  -15 AAPL @ $300
+  Assets:Bank
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let prices = prices_from(input);
+        let sell_date = date(2020, 3, 10);
+        let cost_of = |method| {
+            LotTracker::new(&ledger, "$", method, &prices).disposal_cost_basis(
+                2,
+                0,
+                &ledger.transactions[2],
+            )
+        };
+
+        assert_eq!(cost_of(LotMethod::Fifo), Decimal::new(2000, 0));
+        assert_eq!(cost_of(LotMethod::Lifo), Decimal::new(2500, 0));
+        assert_eq!(cost_of(LotMethod::Average), Decimal::new(2250, 0));
+
+        let tracker = LotTracker::new(&ledger, "$", LotMethod::Fifo, &prices);
+        assert_eq!(
+            tracker.lots_at("Assets:Broker", "AAPL", sell_date),
+            vec![Lot {
+                date: date(2020, 2, 10),
+                quantity: Decimal::new(5, 0),
+                unit_cost: Decimal::new(200, 0),
+            }]
+        );
+        // hypothetical disposal of the remaining lot and more
+        assert_eq!(
+            tracker.cost_basis_of("Assets:Broker", "AAPL", Decimal::new(6, 0), sell_date),
+            Decimal::new(1000, 0)
+        );
+    }
+
+    #[test]
+    fn test_lot_tracker_posting_prices() {
+        let input = r#"
+2020-01-10 Buy
+  Assets:Crypto  2000 ADA @ $0.02
+  Assets:Bank
+
+2020-01-11 Buy
+  Assets:Crypto  1 BTC {{8000 EUR}}
+  Assets:Bank
+
+2020-03-10 Sell
+  Assets:Crypto  -1000 ADA @ $0.05
+  Assets:Bank
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let cost_of_ada = |tracker: &LotTracker| {
+            tracker.cost_basis_of(
+                "Assets:Crypto",
+                "ADA",
+                Decimal::new(1000, 0),
+                date(2020, 3, 10),
+            )
+        };
+
+        // posting prices take precedence over market prices
+        let prices = prices_from(
+            r#"
+P 2020-01-10 16:00:00 ADA $0.03
+P 2020-01-11 00:00:00 EUR $1.10
+"#,
+        );
+        let tracker = LotTracker::new(&ledger, "$", LotMethod::Fifo, &prices);
+        assert_eq!(cost_of_ada(&tracker), Decimal::new(20, 0));
+        assert_eq!(
+            tracker.lots_at("Assets:Crypto", "BTC", date(2020, 3, 10))[0].unit_cost,
+            Decimal::new(8800, 0)
+        );
+        assert!(tracker.unpriced_lots().is_empty());
+
+        // without market prices, only the lot priced in a foreign commodity is unpriced
+        let tracker = LotTracker::new(&ledger, "$", LotMethod::Fifo, &Prices::new());
+        assert_eq!(cost_of_ada(&tracker), Decimal::new(20, 0));
+        let unpriced: Vec<_> = tracker
+            .unpriced_lots()
+            .iter()
+            .map(|lot| lot.commodity.as_str())
+            .collect();
+        assert_eq!(unpriced, vec!["BTC"]);
+    }
+
+    #[test]
+    fn test_lot_tracker_same_day_disposals() {
+        let input = r#"
+2020-01-10 Opening balance
+  Assets:Broker  3 MSFT
+  Equity:Opening
+
+2020-01-10 Buy
+  Assets:Broker  5 AAPL @ $100
+  Assets:Bank
+
+2020-02-10 Buy
+  Assets:Broker  10 AAPL @ $200
+  Assets:Bank
+
+2020-03-10 Sell
+  Assets:Broker  -5 AAPL @ $300
+  Assets:Bank
+
+2020-03-10 Sell again
+  Assets:Broker  -5 AAPL @ $300
+  Assets:Bank
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let tracker = LotTracker::new(&ledger, "$", LotMethod::Fifo, &prices_from(input));
+
+        assert_eq!(
+            tracker.disposal_cost_basis(3, 0, &ledger.transactions[3]),
+            Decimal::new(500, 0)
+        );
+        assert_eq!(
+            tracker.disposal_cost_basis(4, 0, &ledger.transactions[4]),
+            Decimal::new(1000, 0)
+        );
+
+        assert_eq!(
+            tracker.unpriced_lots(),
+            &[UnpricedLot {
+                account: "Assets:Broker".to_string(),
+                commodity: "MSFT".to_string(),
+                lot: Lot {
+                    date: date(2020, 1, 10),
+                    quantity: Decimal::new(3, 0),
+                    unit_cost: Decimal::ZERO,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_realize_gains() {
+        let mut ledger: Ledger = r#"
+2020-01-10 Buy ADA
+  Assets:Crypto  2000 ADA @ $0.02
+  Assets:Bank
+
+2020-03-10 Sell ADA
+  Assets:Crypto  -1000 ADA @ $0.05
+  Assets:Bank
+
+2020-03-15 Salary
+  Income:Salary  -1000 EUR
+  Assets:Bank  $1100
+"#
+        .parse()
+        .unwrap();
+        // market price differs from the one the ADA was sold for
+        let prices = prices_from("P 2020-03-10 00:00:00 ADA $0.04\n");
+        let cost_basis = UnitCost(HashMap::from([("ADA", Decimal::new(2, 2))]));
+
+        let skipped = realize_gains(
+            &mut ledger,
+            &cost_basis,
+            "Income:CapitalGains",
+            "$",
+            2,
+            &|account: &str| account.starts_with("Assets"),
+            &prices,
+        );
+
+        assert!(skipped.is_empty());
+        assert_eq!(ledger.transactions[0].postings.len(), 2);
+        let sell = &ledger.transactions[1];
+        assert_eq!(sell.postings.len(), 3);
+        assert_eq!(sell.postings[2].account, "Income:CapitalGains");
+        assert_eq!(sell.postings[2].amount.to_string(), "$-30.00");
+        assert_eq!(ledger.transactions[2].postings.len(), 2);
+
+        // the ADA is sold at cost, so the transaction balances
+        assert_eq!(
+            sell.to_string(),
+            "2020-03-10 Sell ADA\n  Assets:Crypto  -1000 ADA {$0.02}\n  Assets:Bank  $50.00\n  Income:CapitalGains  $-30.00  ; Auto-generated"
+        );
+        let parsed: Ledger = ledger.to_string().parse().unwrap();
+        assert_eq!(parsed.transactions, ledger.transactions);
+    }
+
+    #[test]
+    fn test_realize_gains_several_disposals() {
+        let mut ledger: Ledger = r#"
+2020-03-10 Sell everything
+  Assets:Crypto  -1000 ADA @@ $50
+  Assets:Crypto  -1 BTC @@ $10000
+  Assets:Bank  $10050
+
+2020-03-11 Sell more
+  Assets:Crypto  -1000 ADA @@ $50
+  Assets:Crypto  -1000 DOGE
+  Assets:Bank  $100
+
+2020-03-12 Sell at prices with more decimal points
+  Assets:Crypto  -1000 DOGE @@ $10.005
+  Assets:Crypto  -1000 XRP @@ $10.005
+  Assets:Crypto  -1000 SOL @@ $9.99
+  Assets:Bank  $30
+"#
+        .parse()
+        .unwrap();
+        // market prices differ from the ones in postings
+        let prices = prices_from(
+            r#"
+P 2020-03-10 00:00:00 ADA $0.04
+P 2020-03-10 00:00:00 BTC $9960
+"#,
+        );
+        let cost_basis = UnitCost(HashMap::from([
+            ("ADA", Decimal::new(2, 2)),
+            ("BTC", Decimal::new(9000, 0)),
+            ("DOGE", Decimal::new(5, 3)),
+            ("XRP", Decimal::new(5, 3)),
+            ("SOL", Decimal::new(5, 3)),
+        ]));
+
+        let skipped = realize_gains(
+            &mut ledger,
+            &cost_basis,
+            "Income:CapitalGains",
+            "$",
+            2,
+            &|account: &str| account.starts_with("Assets"),
+            &prices,
+        );
+
+        // no DOGE price to split the proceeds, other transactions are still processed
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, 1);
+        assert_eq!(ledger.transactions[1].postings.len(), 3);
+
+        let gains = |transaction: &Transaction, first| -> Vec<_> {
+            transaction.postings[first..]
+                .iter()
+                .map(|posting| posting.amount.to_string())
+                .collect()
+        };
+        // proceeds are split by the posting prices
+        assert_eq!(
+            gains(&ledger.transactions[0], 3),
+            vec!["$-30.00", "$-1000.00"]
+        );
+        // proceeds are rounded, the rounding remainder goes to the last disposal
+        assert_eq!(
+            gains(&ledger.transactions[2], 4),
+            vec!["$-5.01", "$-5.01", "$-4.98"]
+        );
+
+        ledger.transactions.remove(1);
+        let parsed: Ledger = ledger.to_string().parse().unwrap();
+        assert_eq!(parsed.transactions, ledger.transactions);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::{Amount, Commodity, Error, Ledger, Posting, Reality, Transaction, TransactionStatus};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+
+/// Describes how rows of a bank CSV are turned into transactions.
+///
+/// Every row becomes a transaction with two postings: the amount goes to `account`
+/// and is balanced by `offset_account`.
+#[derive(Debug, Clone)]
+pub struct CsvMapping {
+    pub account: String,
+    pub offset_account: String,
+    pub commodity: Commodity,
+
+    /// Zero-based column indices.
+    pub date_column: usize,
+    pub payee_column: usize,
+    pub amount_column: usize,
+
+    /// `chrono` format of dates, `%Y-%m-%d` by default.
+    pub date_format: String,
+    pub decimal_separator: char,
+    pub delimiter: char,
+
+    /// Whether the first row is a header to skip, `true` by default.
+    pub has_header: bool,
+}
+
+impl CsvMapping {
+    /// Mapping of `date,payee,amount` rows.
+    pub fn new(account: &str, offset_account: &str, commodity: Commodity) -> Self {
+        CsvMapping {
+            account: account.to_string(),
+            offset_account: offset_account.to_string(),
+            commodity,
+            date_column: 0,
+            payee_column: 1,
+            amount_column: 2,
+            date_format: "%Y-%m-%d".to_string(),
+            decimal_separator: '.',
+            delimiter: ',',
+            has_header: true,
+        }
+    }
+
+    pub fn with_columns(mut self, date: usize, payee: usize, amount: usize) -> Self {
+        self.date_column = date;
+        self.payee_column = payee;
+        self.amount_column = amount;
+        self
+    }
+
+    pub fn with_date_format(mut self, date_format: &str) -> Self {
+        self.date_format = date_format.to_string();
+        self
+    }
+
+    pub fn with_decimal_separator(mut self, decimal_separator: char) -> Self {
+        self.decimal_separator = decimal_separator;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    fn parse_row(&self, line: &str) -> Result<Transaction, String> {
+        let columns = split_csv_line(line, self.delimiter);
+        let column = |index: usize| {
+            columns
+                .get(index)
+                .map(|column| column.trim())
+                .ok_or_else(|| format!("missing column {}", index))
+        };
+
+        let date_column = column(self.date_column)?;
+        let date = NaiveDate::parse_from_str(date_column, &self.date_format)
+            .map_err(|e| format!("invalid date '{}': {}", date_column, e))?;
+        let payee = column(self.payee_column)?;
+        let quantity = self.parse_quantity(column(self.amount_column)?)?;
+
+        let posting = |account: &str, quantity: Decimal| Posting {
+            date,
+            effective_date: date,
+            account: account.to_string(),
+            reality: Reality::Real,
+            amount: Amount {
+                quantity,
+                commodity: self.commodity.clone(),
+            },
+            lot_price: None,
+            price: None,
+            balance: None,
+            status: None,
+            comment: None,
+            tags: Vec::new(),
+        };
+
+        Ok(Transaction {
+            comment: None,
+            date,
+            effective_date: date,
+            status: None,
+            code: None,
+            description: payee.to_string(),
+            tags: Vec::new(),
+            postings: vec![
+                posting(&self.account, quantity),
+                posting(&self.offset_account, -quantity),
+            ],
+        })
+    }
+
+    /// Parses the amount, ignoring whitespace and thousands separators.
+    fn parse_quantity(&self, amount: &str) -> Result<Decimal, String> {
+        let thousands_separator = if self.decimal_separator == '.' {
+            ','
+        } else {
+            '.'
+        };
+        let normalized: String = amount
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != thousands_separator)
+            .map(|c| if c == self.decimal_separator { '.' } else { c })
+            .collect();
+        Decimal::from_str(&normalized).map_err(|e| format!("invalid amount '{}': {}", amount, e))
+    }
+}
+
+impl Ledger {
+    /// Reads transactions from CSV rows, as described by `mapping`.
+    /// Empty lines are skipped. Quoted fields can't span multiple lines.
+    pub fn from_csv<R: Read>(reader: R, mapping: &CsvMapping) -> Result<Ledger, Error> {
+        let mut transactions = Vec::new();
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.map_err(|e| Error::InvalidCsvRow {
+                line: line_number,
+                message: e.to_string(),
+            })?;
+
+            if line.trim().is_empty() || line_number == 1 && mapping.has_header {
+                continue;
+            }
+
+            transactions.push(mapping.parse_row(&line).map_err(|message| {
+                Error::InvalidCsvRow {
+                    line: line_number,
+                    message,
+                }
+            })?);
+        }
+
+        Ok(Ledger {
+            commodity_prices: Vec::new(),
+            transactions,
+            comments: Vec::new(),
+            default_commodity: None,
+            declared_accounts: Vec::new(),
+            declared_commodities: Vec::new(),
+        })
+    }
+
+    /// Writes one row per posting with columns
+    /// `date,description,account,commodity,quantity,status,comment`, preceded by a header row.
+    ///
+    /// Quantities are written without thousands separators. Status is `*` (cleared),
+    /// `!` (pending) or empty; posting status overrides the status of its transaction.
+    pub fn to_csv<W: io::Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writeln!(
+            writer,
+            "date,description,account,commodity,quantity,status,comment"
+        )?;
+
+        for transaction in &self.transactions {
+            for posting in &transaction.postings {
+                let status = match posting.status.or(transaction.status) {
+                    Some(TransactionStatus::Cleared) => "*",
+                    Some(TransactionStatus::Pending) => "!",
+                    None => "",
+                };
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    posting.date.format("%Y-%m-%d"),
+                    escape_csv_field(&transaction.description),
+                    escape_csv_field(&posting.account),
+                    escape_csv_field(&posting.amount.commodity.name),
+                    posting.amount.quantity,
+                    status,
+                    escape_csv_field(posting.comment.as_deref().unwrap_or_default()),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the line on `delimiter`, honoring double-quoted fields.
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.next_if_eq(&'"').is_some() {
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+/// Quotes the field if it contains a comma, quote or line break.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommodityPosition;
+
+    #[test]
+    fn test_from_csv() {
+        let csv = r#"Date;Payee;Title;Amount
+15.01.2020;"Employer; Inc.";Salary;"1.234,50"
+
+20.01.2020;Shop;Food;-5,25
+"#;
+        let mapping = CsvMapping::new(
+            "Assets:Bank",
+            "Expenses:Unknown",
+            Commodity {
+                name: "PLN".to_string(),
+                position: CommodityPosition::Right,
+            },
+        )
+        .with_columns(0, 1, 3)
+        .with_date_format("%d.%m.%Y")
+        .with_decimal_separator(',')
+        .with_delimiter(';');
+
+        let ledger = Ledger::from_csv(csv.as_bytes(), &mapping).unwrap();
+        let expected: Ledger = r#"
+2020-01-15 Employer; Inc.
+  Assets:Bank  1234.50 PLN
+  Expenses:Unknown  -1234.50 PLN
+
+2020-01-20 Shop
+  Assets:Bank  -5.25 PLN
+  Expenses:Unknown  5.25 PLN
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(ledger, expected);
+    }
+
+    #[test]
+    fn test_from_csv_invalid_row() {
+        let csv = "date,payee,amount\n2020-01-15,Shop,abc\n";
+        let mapping = CsvMapping::new(
+            "Assets:Bank",
+            "Expenses:Unknown",
+            Commodity {
+                name: "$".to_string(),
+                position: CommodityPosition::Left,
+            },
+        );
+        assert_eq!(
+            Ledger::from_csv(csv.as_bytes(), &mapping),
+            Err(Error::InvalidCsvRow {
+                line: 2,
+                message: "invalid amount 'abc': Invalid decimal: unknown character".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let ledger: Ledger = r#"
+2020-01-15 * Salary, January
+  Assets:Bank  1234567.50 PLN
+  ! Income:Salary  ; "bonus" included
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let mut csv = Vec::new();
+        ledger.to_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            r#"date,description,account,commodity,quantity,status,comment
+2020-01-15,"Salary, January",Assets:Bank,PLN,1234567.50,*,
+2020-01-15,"Salary, January",Income:Salary,PLN,-1234567.50,!,"""bonus"" included"
+2020-01-20,Food,Expenses:Food,$,5,,
+2020-01-20,Food,Assets:Bank,$,-5,,
+"#
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Ledger, Tag, TagValue, Transaction};
+use chrono::{Duration, Months, NaiveDate};
+
+/// Name of the transaction tag describing how often the transaction repeats.
+pub const RECURRENCE_TAG: &str = "recur";
+
+/// Name of the tag added to generated transactions.
+pub const GENERATED_TAG: &str = "generated";
+
+/// Generates future instances of recurring transactions, dated after the original
+/// and up to `until` (inclusive), sorted by date.
+///
+/// A transaction is recurring if it has a `recur` tag with value `weekly`, `monthly` or
+/// `yearly`. Posting and effective dates are shifted along with the transaction date.
+/// Generated transactions have the `recur` tag replaced by `generated: true`.
+pub fn forecast(ledger: &Ledger, until: NaiveDate) -> Vec<Transaction> {
+    let mut result = Vec::new();
+
+    for transaction in &ledger.transactions {
+        let recurrence = transaction.tags.iter().find_map(|tag| match tag {
+            Tag {
+                name,
+                value: Some(TagValue::String(value)),
+            } if name == RECURRENCE_TAG => Some(value.trim()),
+            _ => None,
+        });
+        let nth_date = |n: u32| match recurrence {
+            Some("weekly") => Some(transaction.date + Duration::weeks(n as i64)),
+            Some("monthly") => transaction.date.checked_add_months(Months::new(n)),
+            Some("yearly") => transaction.date.checked_add_months(Months::new(12 * n)),
+            _ => None,
+        };
+
+        let mut n = 1;
+        while let Some(date) = nth_date(n).filter(|date| *date <= until) {
+            result.push(instance(transaction, date));
+            n += 1;
+        }
+    }
+
+    result.sort_by_key(|transaction| transaction.date);
+    result
+}
+
+fn instance(transaction: &Transaction, date: NaiveDate) -> Transaction {
+    let shift = date - transaction.date;
+
+    let mut instance = transaction.clone();
+    instance.date = date;
+    instance.effective_date += shift;
+    for posting in &mut instance.postings {
+        posting.date += shift;
+        posting.effective_date += shift;
+    }
+
+    instance.tags.retain(|tag| tag.name != RECURRENCE_TAG);
+    instance.tags.push(Tag {
+        name: GENERATED_TAG.to_string(),
+        value: Some(TagValue::String("true".to_string())),
+    });
+
+    instance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::date;
+    use crate::Serializer;
+
+    #[test]
+    fn test_forecast() {
+        let ledger: Ledger = r#"
+2020-01-31 Rent
+  ; recur: monthly
+  Expenses:Rent  $500
+  Assets:Bank
+
+2020-03-02 Groceries
+  ; recur: weekly
+  Expenses:Food  $50  ; [=2020-03-03]
+  Assets:Bank
+
+2020-02-10 Cinema
+  Expenses:Fun  $20
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let transactions = forecast(&ledger, date(2020, 3, 16));
+        let dates: Vec<_> = transactions
+            .iter()
+            .map(|txn| (txn.date.to_string(), txn.description.as_str()))
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                ("2020-02-29".to_string(), "Rent"),
+                ("2020-03-09".to_string(), "Groceries"),
+                ("2020-03-16".to_string(), "Groceries"),
+            ]
+        );
+
+        let groceries = &transactions[1];
+        assert_eq!(groceries.postings[0].effective_date, date(2020, 3, 10));
+        assert_eq!(
+            groceries.to_string_pretty(&Default::default()),
+            "2020-03-09 Groceries\n  ; generated: true\n  Expenses:Food  $50  ; [=2020-03-10]\n  Assets:Bank  $-50"
+        );
+    }
+}
@@ -1,6 +1,75 @@
 use crate::prices::{Prices, PricesError};
-use crate::{Amount, Commodity, CommodityPosition, Ledger, Posting, Reality, Transaction};
-use rust_decimal::RoundingStrategy;
+use crate::{Amount, AmountExt, CommodityPosition, Ledger, Posting, Reality, Transaction};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::BTreeMap;
+
+const DEFAULT_TRADING_ACCOUNT: &str = "Trading:Exchange";
+
+/// Comment of postings added to the trading account.
+const AUTO_GENERATED_COMMENT: &str = "Auto-generated";
+
+/// Settings for `handle_foreign_currencies_with_config`.
+#[derive(Debug, Clone)]
+pub struct ForeignCurrenciesConfig {
+    pub main_commodity: String,
+    pub main_commodity_decimal_points: u32,
+
+    /// Account used for auto-generated postings, `Trading:Exchange` by default.
+    pub trading_account: String,
+
+    /// Position of the main commodity in converted amounts. If `None`, it's taken
+    /// from the first amount in the main commodity found in the ledger
+    /// (`CommodityPosition::Right` if there are none).
+    pub main_commodity_position: Option<CommodityPosition>,
+
+    /// Rounding of converted amounts, `RoundingStrategy::MidpointAwayFromZero` by default.
+    pub rounding_strategy: RoundingStrategy,
+}
+
+impl ForeignCurrenciesConfig {
+    pub fn new(main_commodity: &str, main_commodity_decimal_points: u32) -> Self {
+        ForeignCurrenciesConfig {
+            main_commodity: main_commodity.to_string(),
+            main_commodity_decimal_points,
+            trading_account: DEFAULT_TRADING_ACCOUNT.to_string(),
+            main_commodity_position: None,
+            rounding_strategy: RoundingStrategy::MidpointAwayFromZero,
+        }
+    }
+
+    pub fn with_trading_account(mut self, trading_account: &str) -> Self {
+        self.trading_account = trading_account.to_string();
+        self
+    }
+
+    pub fn with_main_commodity_position(mut self, position: CommodityPosition) -> Self {
+        self.main_commodity_position = Some(position);
+        self
+    }
+
+    pub fn with_rounding_strategy(mut self, rounding_strategy: RoundingStrategy) -> Self {
+        self.rounding_strategy = rounding_strategy;
+        self
+    }
+
+    fn main_commodity_position_in(&self, ledger: &Ledger) -> CommodityPosition {
+        self.main_commodity_position.unwrap_or_else(|| {
+            ledger
+                .transactions
+                .iter()
+                .flat_map(|transaction| &transaction.postings)
+                .map(|posting| &posting.amount.commodity)
+                .chain(
+                    ledger
+                        .commodity_prices
+                        .iter()
+                        .map(|price| &price.amount.commodity),
+                )
+                .find(|commodity| commodity.name == self.main_commodity)
+                .map_or(CommodityPosition::Right, |commodity| commodity.position)
+        })
+    }
+}
 
 /// Handle foreign currencies.
 /// Generate additional postings for "currency trading accounts".
@@ -19,22 +88,49 @@ where
     F2: Fn(&str) -> bool,
     F3: Fn(&str) -> bool,
 {
+    handle_foreign_currencies_with_config(
+        ledger,
+        is_asset_account,
+        is_income_account,
+        is_expense_account,
+        prices,
+        &ForeignCurrenciesConfig::new(main_commodity, main_commodity_decimal_points),
+    )
+}
+
+/// Like `handle_foreign_currencies`, but with all the settings in `config`.
+pub fn handle_foreign_currencies_with_config<F1, F2, F3>(
+    ledger: &mut Ledger,
+    is_asset_account: &F1,
+    is_income_account: &F2,
+    is_expense_account: &F3,
+    prices: &Prices,
+    config: &ForeignCurrenciesConfig,
+) -> Result<(), PricesError>
+where
+    F1: Fn(&str) -> bool,
+    F2: Fn(&str) -> bool,
+    F3: Fn(&str) -> bool,
+{
+    let main_commodity_position = config.main_commodity_position_in(ledger);
+
     for transaction in &mut ledger.transactions {
         handle_foreign_asset_income(
             transaction,
             is_income_account,
-            main_commodity,
-            main_commodity_decimal_points,
             prices,
+            config,
+            main_commodity_position,
         )?;
-        handle_asset_exchange(transaction, is_asset_account);
+        handle_asset_exchange(transaction, is_asset_account, config);
         handle_foreign_asset_expenses(
             transaction,
             is_expense_account,
-            main_commodity,
-            main_commodity_decimal_points,
             prices,
+            config,
+            main_commodity_position,
         )?;
+        handle_remaining_exchange(transaction, config);
     }
     Ok(())
 }
@@ -46,128 +142,125 @@ where
 fn handle_foreign_asset_income<F>(
     transaction: &mut Transaction,
     is_income_account: &F,
-    main_commodity: &str,
-    main_commodity_decimal_points: u32,
     prices: &Prices,
+    config: &ForeignCurrenciesConfig,
+    main_commodity_position: CommodityPosition,
 ) -> Result<(), PricesError>
 where
     F: Fn(&str) -> bool,
 {
-    let mut new_postings = Vec::new();
-
-    // look for postings that spends foreign commodities
-    for posting in transaction.postings.iter_mut() {
-        if is_income_account(&posting.account) && posting.amount.commodity.name != main_commodity {
-            let foreign_amount = posting.amount.clone();
-
-            // convert amount to main commodity
-            let mut amount_main_commodity = prices.convert(
-                posting.amount.quantity,
-                &posting.amount.commodity.name,
-                main_commodity,
-                transaction.date,
-            )?;
-            amount_main_commodity = amount_main_commodity.round_dp_with_strategy(
-                main_commodity_decimal_points,
-                RoundingStrategy::MidpointAwayFromZero,
-            );
-
-            // replace the value
-            let mut main_currency_amount = Amount {
-                quantity: amount_main_commodity,
-                commodity: Commodity {
-                    name: main_commodity.to_string(),
-                    position: CommodityPosition::Right,
-                },
-            };
-            posting.amount = main_currency_amount.clone();
-
-            // add postings to trading account that will track currency gains and losses
-            main_currency_amount.quantity = -main_currency_amount.quantity;
-            new_postings.push(Posting {
-                date: posting.date,
-                effective_date: posting.effective_date,
-                comment: Some("Auto-generated".to_string()),
-                account: "Trading:Exchange".to_string(),
-                reality: Reality::Real,
-                status: None,
-                amount: main_currency_amount,
-                tags: vec![],
-            });
-            new_postings.push(Posting {
-                date: posting.date,
-                effective_date: posting.effective_date,
-                comment: Some("Auto-generated".to_string()),
-                account: "Trading:Exchange".to_string(),
-                reality: Reality::Real,
-                status: None,
-                amount: foreign_amount,
-                tags: vec![],
-            });
-        }
-    }
-
-    transaction.postings.append(&mut new_postings);
-
-    Ok(())
+    convert_foreign_postings(
+        transaction,
+        is_income_account,
+        prices,
+        config,
+        main_commodity_position,
+        false,
+    )
 }
 
-/// Every time there is an exchange made between assets,
+/// Every time there is an exchange made between two assets
+/// (possibly with additional postings, like fees),
 /// add entries to corresponding currency trading account
 /// so that the value of trading account equals currency gains and losses in time.
-fn handle_asset_exchange<F>(transaction: &mut Transaction, is_asset_account: &F)
-where
+fn handle_asset_exchange<F>(
+    transaction: &mut Transaction,
+    is_asset_account: &F,
+    config: &ForeignCurrenciesConfig,
+) where
     F: Fn(&str) -> bool,
 {
     // is this a transaction between two asset accounts
-    if transaction.postings.len() != 2 {
+    // (other postings, like fees, are allowed)
+    let asset_postings: Vec<_> = transaction
+        .postings
+        .iter()
+        .filter(|posting| is_asset_account(&posting.account))
+        .collect();
+    if asset_postings.len() != 2 {
         return;
     }
 
-    let posting1 = &transaction.postings[0];
-    let posting2 = &transaction.postings[1];
-
-    if !is_asset_account(&posting1.account) || !is_asset_account(&posting2.account) {
+    // is this a transaction between different commodities
+    if asset_postings[0]
+        .amount
+        .same_commodity(&asset_postings[1].amount)
+    {
         return;
     }
 
-    // is this a transaction between different commodities
-    let commodity1_name = &posting1.amount.commodity.name;
-    let commodity2_name = &posting2.amount.commodity.name;
-    if commodity1_name == commodity2_name {
-        return;
+    // add postings to trading account that will track currency gains and losses,
+    // balancing every exchanged commodity within the transaction
+    let mut new_postings = Vec::new();
+    for asset_posting in asset_postings {
+        let mut amount = asset_posting.amount.clone();
+        amount.quantity = -transaction
+            .postings
+            .iter()
+            .filter(|posting| posting.amount.same_commodity(&amount))
+            .map(|posting| posting.amount.quantity)
+            .sum::<Decimal>();
+        if amount.is_zero() {
+            continue;
+        }
+
+        new_postings.push(Posting {
+            date: asset_posting.date,
+            effective_date: asset_posting.effective_date,
+            comment: Some(AUTO_GENERATED_COMMENT.to_string()),
+            account: config.trading_account.clone(),
+            reality: Reality::Real,
+            lot_price: None,
+            price: None,
+            balance: None,
+            status: asset_posting.status,
+            amount,
+            tags: asset_posting.tags.clone(),
+        });
     }
 
-    // add postings to trading account that will track currency gains and losses
-    let mut amount1 = posting1.amount.clone();
-    let mut amount2 = posting2.amount.clone();
+    transaction.postings.append(&mut new_postings);
+}
 
-    amount1.quantity = -amount1.quantity;
-    amount2.quantity = -amount2.quantity;
+/// Transactions mixing several commodities that are not an exchange
+/// between two assets (e.g. foreign expenses paid in main currency)
+/// are balanced in value only. Add entries to currency trading account
+/// for every commodity that doesn't sum up to zero, so the transaction
+/// balances per commodity. Real and balanced virtual postings are balanced
+/// separately, unbalanced virtual postings are skipped.
+fn handle_remaining_exchange(transaction: &mut Transaction, config: &ForeignCurrenciesConfig) {
+    let mut new_postings = Vec::new();
+    for reality in [Reality::Real, Reality::BalancedVirtual] {
+        let mut sums: BTreeMap<&str, Amount> = BTreeMap::new();
+        for posting in &transaction.postings {
+            if posting.reality != reality {
+                continue;
+            }
+            sums.entry(&posting.amount.commodity.name)
+                .and_modify(|sum| sum.quantity += posting.amount.quantity)
+                .or_insert_with(|| posting.amount.clone());
+        }
 
-    let new_posting1 = Posting {
-        date: posting1.date,
-        effective_date: posting1.effective_date,
-        comment: Some("Auto-generated".to_string()),
-        account: "Trading:Exchange".to_string(),
-        reality: Reality::Real,
-        status: posting1.status,
-        amount: amount1,
-        tags: posting1.tags.clone(),
-    };
-    let new_posting2 = Posting {
-        date: posting2.date,
-        effective_date: posting2.effective_date,
-        comment: Some("Auto-generated".to_string()),
-        account: "Trading:Exchange".to_string(),
-        reality: Reality::Real,
-        status: posting2.status,
-        amount: amount2,
-        tags: posting2.tags.clone(),
-    };
+        new_postings.extend(
+            sums.into_values()
+                .filter(|sum| !sum.is_zero())
+                .map(|sum| Posting {
+                    date: transaction.date,
+                    effective_date: transaction.effective_date,
+                    comment: Some(AUTO_GENERATED_COMMENT.to_string()),
+                    account: config.trading_account.clone(),
+                    reality,
+                    lot_price: None,
+                    price: None,
+                    balance: None,
+                    status: None,
+                    amount: sum.negated(),
+                    tags: vec![],
+                }),
+        );
+    }
 
-    transaction.postings.push(new_posting1);
-    transaction.postings.push(new_posting2);
+    transaction.postings.extend(new_postings);
 }
 
 /// Every time there is an expense in foreign currency,
@@ -177,68 +270,398 @@ where
 fn handle_foreign_asset_expenses<F>(
     transaction: &mut Transaction,
     is_expense_account: &F,
-    main_commodity: &str,
-    main_commodity_decimal_points: u32,
     prices: &Prices,
+    config: &ForeignCurrenciesConfig,
+    main_commodity_position: CommodityPosition,
 ) -> Result<(), PricesError>
 where
     F: Fn(&str) -> bool,
 {
-    let mut new_postings = Vec::new();
+    convert_foreign_postings(
+        transaction,
+        is_expense_account,
+        prices,
+        config,
+        main_commodity_position,
+        true,
+    )
+}
+
+/// Converts foreign amounts of matching postings to the main commodity and adds
+/// two trading account postings per converted posting. With `keep_metadata`,
+/// they get the status and tags of the converted posting.
+fn convert_foreign_postings<F>(
+    transaction: &mut Transaction,
+    is_account: &F,
+    prices: &Prices,
+    config: &ForeignCurrenciesConfig,
+    main_commodity_position: CommodityPosition,
+    keep_metadata: bool,
+) -> Result<(), PricesError>
+where
+    F: Fn(&str) -> bool,
+{
+    let is_foreign = |posting: &Posting| {
+        is_account(&posting.account) && posting.amount.commodity.name != config.main_commodity
+    };
+    let count = transaction
+        .postings
+        .iter()
+        .filter(|posting| is_foreign(posting))
+        .count();
+    if count == 0 {
+        return Ok(());
+    }
 
-    // look for postings that spends foreign commodities
+    let mut new_postings = Vec::with_capacity(2 * count);
     for posting in transaction.postings.iter_mut() {
-        if is_expense_account(&posting.account) && posting.amount.commodity.name != main_commodity {
-            let foreign_amount = posting.amount.clone();
-
-            // convert amount to main commodity
-            let mut amount_main_commodity = prices.convert(
-                posting.amount.quantity,
-                &posting.amount.commodity.name,
-                main_commodity,
-                transaction.date,
-            )?;
-            amount_main_commodity = amount_main_commodity.round_dp_with_strategy(
-                main_commodity_decimal_points,
-                RoundingStrategy::MidpointAwayFromZero,
-            );
-
-            // replace the value
-            let mut main_currency_amount = Amount {
-                quantity: amount_main_commodity,
-                commodity: Commodity {
-                    name: main_commodity.to_string(),
-                    position: CommodityPosition::Right,
-                },
-            };
-            posting.amount = main_currency_amount.clone();
-
-            // add postings to trading account that will track currency gains and losses
-            main_currency_amount.quantity = -main_currency_amount.quantity;
-            new_postings.push(Posting {
-                date: posting.date,
-                effective_date: posting.effective_date,
-                comment: Some("Auto-generated".to_string()),
-                account: "Trading:Exchange".to_string(),
-                reality: Reality::Real,
-                status: posting.status,
-                amount: main_currency_amount,
-                tags: posting.tags.clone(),
-            });
-            new_postings.push(Posting {
-                date: posting.date,
-                effective_date: posting.effective_date,
-                comment: Some("Auto-generated".to_string()),
-                account: "Trading:Exchange".to_string(),
-                reality: Reality::Real,
-                status: posting.status,
-                amount: foreign_amount,
-                tags: posting.tags.clone(),
-            });
+        if !is_foreign(posting) {
+            continue;
         }
+
+        // convert amount to main commodity
+        let mut main_currency_amount = prices.convert_amount(
+            &posting.amount,
+            &config.main_commodity,
+            main_commodity_position,
+            transaction.date,
+        )?;
+        main_currency_amount.quantity = main_currency_amount.quantity.round_dp_with_strategy(
+            config.main_commodity_decimal_points,
+            config.rounding_strategy,
+        );
+
+        // replace the value, prices and balance assertion of the foreign amount no longer apply
+        let foreign_amount = std::mem::replace(&mut posting.amount, main_currency_amount);
+        posting.lot_price = None;
+        posting.price = None;
+        posting.balance = None;
+
+        // add postings to trading account that will track currency gains and losses
+        let (status, tags) = if keep_metadata {
+            (posting.status, posting.tags.clone())
+        } else {
+            (None, Vec::new())
+        };
+        new_postings.push(Posting {
+            date: posting.date,
+            effective_date: posting.effective_date,
+            comment: Some(AUTO_GENERATED_COMMENT.to_string()),
+            account: config.trading_account.clone(),
+            reality: Reality::Real,
+            lot_price: None,
+            price: None,
+            balance: None,
+            status,
+            amount: posting.amount.negated(),
+            tags: tags.clone(),
+        });
+        new_postings.push(Posting {
+            date: posting.date,
+            effective_date: posting.effective_date,
+            comment: Some(AUTO_GENERATED_COMMENT.to_string()),
+            account: config.trading_account.clone(),
+            reality: Reality::Real,
+            lot_price: None,
+            price: None,
+            balance: None,
+            status,
+            amount: foreign_amount,
+            tags,
+        });
     }
 
     transaction.postings.append(&mut new_postings);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::prices_from;
+
+    fn postings(transaction: &Transaction) -> Vec<(String, String)> {
+        transaction
+            .postings
+            .iter()
+            .map(|p| (p.account.clone(), p.amount.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_trading_account() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Groceries
+  Expenses:Food  10 EUR
+  Assets:Cash
+"#
+        .parse()
+        .unwrap();
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR 4.50 PLN\n");
+
+        handle_foreign_currencies_with_config(
+            &mut ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Income"),
+            &|account: &str| account.starts_with("Expenses"),
+            &prices,
+            &ForeignCurrenciesConfig::new("PLN", 2)
+                .with_trading_account("Equity:Trading:Currencies"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            postings(&ledger.transactions[0]),
+            vec![
+                ("Expenses:Food".to_string(), "45.00 PLN".to_string()),
+                ("Assets:Cash".to_string(), "-10 EUR".to_string()),
+                (
+                    "Equity:Trading:Currencies".to_string(),
+                    "-45.00 PLN".to_string()
+                ),
+                (
+                    "Equity:Trading:Currencies".to_string(),
+                    "10 EUR".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_main_commodity_position() {
+        let input = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-16 Groceries
+  Expenses:Food  10 EUR
+  Assets:Cash
+"#;
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR $1.10\n");
+        let handle = |ledger: &mut Ledger, config: &ForeignCurrenciesConfig| {
+            handle_foreign_currencies_with_config(
+                ledger,
+                &|account: &str| account.starts_with("Assets"),
+                &|account: &str| account.starts_with("Income"),
+                &|account: &str| account.starts_with("Expenses"),
+                &prices,
+                config,
+            )
+            .unwrap();
+        };
+
+        let mut ledger: Ledger = input.parse().unwrap();
+        handle(&mut ledger, &ForeignCurrenciesConfig::new("$", 2));
+        assert_eq!(
+            ledger.transactions[1].postings[0].amount.to_string(),
+            "$11.00"
+        );
+
+        let mut ledger: Ledger = input.parse().unwrap();
+        handle(
+            &mut ledger,
+            &ForeignCurrenciesConfig::new("$", 2)
+                .with_main_commodity_position(CommodityPosition::Right),
+        );
+        assert_eq!(
+            ledger.transactions[1].postings[0].amount.to_string(),
+            "11.00 $"
+        );
+    }
+
+    #[test]
+    fn test_rounding_strategy() {
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR 4.5 PLN\n");
+        let handle = |config: &ForeignCurrenciesConfig| {
+            let mut ledger: Ledger = r#"
+2020-01-16 Groceries
+  Expenses:Food  0.25 EUR
+  Assets:Cash
+"#
+            .parse()
+            .unwrap();
+            handle_foreign_currencies_with_config(
+                &mut ledger,
+                &|account: &str| account.starts_with("Assets"),
+                &|account: &str| account.starts_with("Income"),
+                &|account: &str| account.starts_with("Expenses"),
+                &prices,
+                config,
+            )
+            .unwrap();
+            ledger.transactions[0].postings[0].amount.quantity
+        };
+
+        assert_eq!(
+            handle(&ForeignCurrenciesConfig::new("PLN", 2)),
+            Decimal::new(113, 2)
+        );
+        assert_eq!(
+            handle(
+                &ForeignCurrenciesConfig::new("PLN", 2)
+                    .with_rounding_strategy(RoundingStrategy::MidpointNearestEven)
+            ),
+            Decimal::new(112, 2)
+        );
+    }
+
+    #[test]
+    fn test_asset_exchange_with_fee() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Exchange
+  Assets:Bank:USD  $-101
+  Assets:Bank:EUR  90 EUR
+  Expenses:Fees  $1
+"#
+        .parse()
+        .unwrap();
+
+        handle_foreign_currencies(
+            &mut ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Income"),
+            &|account: &str| account.starts_with("Expenses"),
+            "$",
+            2,
+            &Prices::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            postings(&ledger.transactions[0])[3..],
+            vec![
+                ("Trading:Exchange".to_string(), "$100".to_string()),
+                ("Trading:Exchange".to_string(), "-90 EUR".to_string()),
+            ]
+        );
+
+        let mut balance = crate::account_balance::AccountBalance::new();
+        for posting in &ledger.transactions[0].postings {
+            balance += &posting.amount;
+        }
+        assert!(balance.is_zero());
+    }
+
+    #[test]
+    fn test_multiple_foreign_expenses() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Holidays
+  Expenses:Food  10 EUR @@ 45 PLN
+  Expenses:Travel  20 CHF @@ 88 PLN
+  Assets:Bank  -133 PLN
+"#
+        .parse()
+        .unwrap();
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.50 PLN
+P 2020-01-01 00:00:00 CHF 4.50 PLN
+"#,
+        );
+
+        handle_foreign_currencies(
+            &mut ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Income"),
+            &|account: &str| account.starts_with("Expenses"),
+            "PLN",
+            2,
+            &prices,
+        )
+        .unwrap();
+
+        let transaction = &ledger.transactions[0];
+        assert_eq!(
+            postings(transaction)[..3],
+            vec![
+                ("Expenses:Food".to_string(), "45.00 PLN".to_string()),
+                ("Expenses:Travel".to_string(), "90.00 PLN".to_string()),
+                ("Assets:Bank".to_string(), "-133 PLN".to_string()),
+            ]
+        );
+
+        let mut balance = crate::account_balance::AccountBalance::new();
+        for posting in &transaction.postings {
+            balance += &posting.amount;
+        }
+        assert!(balance.is_zero());
+
+        // the difference between 88 PLN paid and 90 PLN value of CHF ends up in trading account
+        let mut trading = crate::account_balance::AccountBalance::new();
+        for posting in &transaction.postings {
+            if posting.account == "Trading:Exchange" {
+                trading += &posting.amount;
+            }
+        }
+        assert_eq!(trading.to_string(), "-2.00 PLN");
+    }
+
+    #[test]
+    fn test_remaining_exchange_ignores_virtual_postings() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Groceries
+  Expenses:Food  10 PLN
+  Assets:Bank
+  (Budget:Food)  -10 PLN
+
+2020-01-16 Holidays
+  Expenses:Food  10 EUR @@ 45 PLN
+  Assets:Bank  -45 PLN
+  [Budget:Food]  10 EUR @@ 45 PLN
+  [Budget:Available]  -45 PLN
+"#
+        .parse()
+        .unwrap();
+        let original = ledger.transactions[0].clone();
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR 4.50 PLN\n");
+
+        handle_foreign_currencies(
+            &mut ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Income"),
+            &|account: &str| account.starts_with("Expenses"),
+            "PLN",
+            2,
+            &prices,
+        )
+        .unwrap();
+
+        assert_eq!(ledger.transactions[0], original);
+
+        for reality in [Reality::Real, Reality::BalancedVirtual] {
+            let mut balance = crate::account_balance::AccountBalance::new();
+            for posting in &ledger.transactions[1].postings {
+                if posting.reality == reality {
+                    balance += &posting.amount;
+                }
+            }
+            assert!(balance.is_zero(), "{:?}: {}", reality, balance);
+        }
+    }
+
+    #[test]
+    fn test_asset_exchange_ignores_single_asset() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Groceries
+  Assets:Bank:USD  $-10
+  Expenses:Food  $10
+"#
+        .parse()
+        .unwrap();
+        let original = ledger.clone();
+
+        handle_foreign_currencies(
+            &mut ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Income"),
+            &|account: &str| account.starts_with("Expenses"),
+            "$",
+            2,
+            &Prices::new(),
+        )
+        .unwrap();
+        assert_eq!(ledger, original);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::balance::{Balance, DateMode};
+use crate::prices::{Prices, PricesError};
+use crate::Ledger;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Value of all accounts starting with `prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountGroupTotal {
+    pub prefix: String,
+    pub value: Decimal,
+}
+
+/// Profit & loss over a period, valued in a single commodity.
+///
+/// Income is reported as a positive number (ledger keeps it negative),
+/// so `net = total_income - total_expenses`.
+#[derive(Debug, Clone)]
+pub struct IncomeStatement {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub commodity: String,
+    pub income: Vec<AccountGroupTotal>,
+    pub expenses: Vec<AccountGroupTotal>,
+    pub total_income: Decimal,
+    pub total_expenses: Decimal,
+    pub net: Decimal,
+}
+
+/// Builds an income statement for postings dated within `[start, end]`.
+///
+/// Every prefix forms its own group. Amounts are valued at prices from the end of the period.
+pub fn income_statement(
+    ledger: &Ledger,
+    period: (NaiveDate, NaiveDate),
+    income_prefixes: &[&str],
+    expense_prefixes: &[&str],
+    commodity: &str,
+    prices: &Prices,
+) -> Result<IncomeStatement, PricesError> {
+    let (start, end) = period;
+    let balance = Balance::in_range(ledger, start, end, DateMode::Posting);
+
+    let group_totals = |prefixes: &[&str], sign: Decimal| {
+        prefixes
+            .iter()
+            .map(|prefix| {
+                let value = balance
+                    .get_account_balance(&[prefix])
+                    .value_in_commodity(commodity, end, prices)?;
+                Ok(AccountGroupTotal {
+                    prefix: prefix.to_string(),
+                    value: sign * value,
+                })
+            })
+            .collect::<Result<Vec<_>, PricesError>>()
+    };
+
+    let income = group_totals(income_prefixes, Decimal::NEGATIVE_ONE)?;
+    let expenses = group_totals(expense_prefixes, Decimal::ONE)?;
+
+    // groups may overlap, so totals are computed from the whole balance
+    let total_income = -balance
+        .get_account_balance(income_prefixes)
+        .value_in_commodity(commodity, end, prices)?;
+    let total_expenses = balance
+        .get_account_balance(expense_prefixes)
+        .value_in_commodity(commodity, end, prices)?;
+
+    Ok(IncomeStatement {
+        start,
+        end,
+        commodity: commodity.to_string(),
+        income,
+        expenses,
+        total_income,
+        total_expenses,
+        net: total_income - total_expenses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+
+    #[test]
+    fn test_income_statement() {
+        let input = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+2019-12-20 Salary
+  Assets:Bank  1000 PLN
+  Income:Salary
+
+2020-01-10 Salary
+  Assets:Bank  5000 PLN
+  Income:Salary
+
+2020-01-12 Interest
+  Assets:Bank  10 EUR
+  Income:Interest
+
+2020-01-15 Rent
+  Expenses:Rent  2000 PLN
+  Assets:Bank
+
+2020-01-20 Food
+  Expenses:Food  50 EUR
+  Assets:Bank
+
+2020-02-01 Rent
+  Expenses:Rent  2000 PLN
+  Assets:Bank
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let prices = prices_from(input);
+
+        let statement = income_statement(
+            &ledger,
+            (date(2020, 1, 1), date(2020, 1, 31)),
+            &["Income:Salary", "Income:Interest"],
+            &["Expenses"],
+            "PLN",
+            &prices,
+        )
+        .unwrap();
+
+        assert_eq!(
+            statement.income,
+            vec![
+                AccountGroupTotal {
+                    prefix: "Income:Salary".to_string(),
+                    value: Decimal::new(5000, 0),
+                },
+                AccountGroupTotal {
+                    prefix: "Income:Interest".to_string(),
+                    value: Decimal::new(40, 0),
+                },
+            ]
+        );
+        assert_eq!(statement.expenses[0].value, Decimal::new(2200, 0));
+        assert_eq!(statement.total_income, Decimal::new(5040, 0));
+        assert_eq!(statement.total_expenses, Decimal::new(2200, 0));
+        assert_eq!(statement.net, Decimal::new(2840, 0));
+    }
+
+    #[test]
+    fn test_income_statement_missing_price() {
+        let ledger: Ledger = r#"
+2020-01-12 Interest
+  Assets:Bank  10 EUR
+  Income:Interest
+"#
+        .parse()
+        .unwrap();
+
+        assert!(income_statement(
+            &ledger,
+            (date(2020, 1, 1), date(2020, 1, 31)),
+            &["Income"],
+            &["Expenses"],
+            "PLN",
+            &Prices::new(),
+        )
+        .is_err());
+    }
+}
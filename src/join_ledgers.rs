@@ -1,9 +1,120 @@
-use crate::Ledger;
+use crate::{Amount, Ledger};
+use chrono::NaiveDate;
+use ledger_parser::{Tag, TagValue};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    ConflictingPrice {
+        date: NaiveDate,
+        commodity: String,
+        a: Amount,
+        b: Amount,
+    },
+}
+
+impl std::error::Error for JoinError {}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::ConflictingPrice {
+                date,
+                commodity,
+                a,
+                b,
+            } => {
+                write!(
+                    f,
+                    "Conflicting prices for {} on {}: {} and {}",
+                    commodity, date, a, b
+                )
+            }
+        }
+    }
+}
+
+/// Order used when merging ledgers in `join_ledgers_sorted`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    /// Transactions by date, commodity prices by datetime.
+    /// Entries with equal keys keep their concatenation order.
+    #[default]
+    Date,
+
+    /// Transactions by `(date, effective_date, description)`,
+    /// commodity prices by `(datetime, commodity_name)`.
+    DateEffectiveDescription,
+}
 
 pub fn join_ledgers(ledgers: Vec<Ledger>) -> Ledger {
+    join_ledgers_sorted(ledgers, SortKey::Date)
+}
+
+/// Like `join_ledgers`, but fails if there are two commodity prices
+/// for the same commodity in the same target commodity on the same date
+/// with different amounts.
+pub fn try_join_ledgers(ledgers: Vec<Ledger>) -> Result<Ledger, JoinError> {
+    let ledger = join_ledgers(ledgers);
+
+    let mut seen: HashMap<(NaiveDate, &str, &str), &Amount> = HashMap::new();
+    for price in &ledger.commodity_prices {
+        let key = (
+            price.datetime.date(),
+            price.commodity_name.as_str(),
+            price.amount.commodity.name.as_str(),
+        );
+        match seen.get(&key) {
+            Some(&amount) if *amount != price.amount => {
+                return Err(JoinError::ConflictingPrice {
+                    date: key.0,
+                    commodity: price.commodity_name.clone(),
+                    a: amount.clone(),
+                    b: price.amount.clone(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(key, &price.amount);
+            }
+        }
+    }
+
+    Ok(ledger)
+}
+
+/// Like `join_ledgers`, but every posting gets a `source` tag
+/// with the name of the ledger it came from. Existing tags are preserved.
+pub fn join_ledgers_tagged(named_ledgers: Vec<(String, Ledger)>) -> Ledger {
+    let ledgers = named_ledgers
+        .into_iter()
+        .map(|(name, mut ledger)| {
+            for transaction in &mut ledger.transactions {
+                for posting in &mut transaction.postings {
+                    posting.tags.push(Tag {
+                        name: "source".to_string(),
+                        value: Some(TagValue::String(name.clone())),
+                    });
+                }
+            }
+            ledger
+        })
+        .collect();
+
+    join_ledgers(ledgers)
+}
+
+/// Like `join_ledgers`, but with a configurable ordering, so that
+/// same-date entries from different sources are ordered deterministically.
+pub fn join_ledgers_sorted(ledgers: Vec<Ledger>, sort_key: SortKey) -> Ledger {
     let mut ledger = Ledger {
         commodity_prices: Vec::new(),
         transactions: Vec::new(),
+        comments: Vec::new(),
+        default_commodity: None,
+        declared_accounts: Vec::new(),
+        declared_commodities: Vec::new(),
     };
 
     for mut src_ledger in ledgers {
@@ -11,10 +122,155 @@ pub fn join_ledgers(ledgers: Vec<Ledger>) -> Ledger {
             .commodity_prices
             .append(&mut src_ledger.commodity_prices);
         ledger.transactions.append(&mut src_ledger.transactions);
+        ledger.comments.append(&mut src_ledger.comments);
+        // like in a single file, the last `D` directive is kept
+        if src_ledger.default_commodity.is_some() {
+            ledger.default_commodity = src_ledger.default_commodity;
+        }
+        for name in src_ledger.declared_accounts {
+            if !ledger.declared_accounts.contains(&name) {
+                ledger.declared_accounts.push(name);
+            }
+        }
+        for name in src_ledger.declared_commodities {
+            if !ledger.declared_commodities.contains(&name) {
+                ledger.declared_commodities.push(name);
+            }
+        }
     }
 
-    ledger.commodity_prices.sort_by_key(|price| price.datetime);
-    ledger.transactions.sort_by_key(|txn| txn.date);
+    // comments at the end of documents go last
+    ledger
+        .comments
+        .sort_by_key(|comment| (comment.date.is_none(), comment.date));
+
+    match sort_key {
+        SortKey::Date => {
+            ledger.commodity_prices.sort_by_key(|price| price.datetime);
+            ledger.transactions.sort_by_key(|txn| txn.date);
+        }
+        SortKey::DateEffectiveDescription => {
+            ledger.commodity_prices.sort_by(|a, b| {
+                (a.datetime, &a.commodity_name).cmp(&(b.datetime, &b.commodity_name))
+            });
+            ledger.transactions.sort_by(|a, b| {
+                (a.date, a.effective_date, &a.description).cmp(&(
+                    b.date,
+                    b.effective_date,
+                    &b.description,
+                ))
+            });
+        }
+    }
 
     ledger
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledgers() -> Vec<Ledger> {
+        let ledger1: Ledger = r#"
+P 2020-01-01 00:00:00 USD 4.00 PLN
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+        let ledger2: Ledger = r#"
+P 2020-01-01 00:00:00 EUR 4.50 PLN
+
+2020-01-15 Food
+  Expenses:Food  $5
+  Assets:Bank
+
+2020-01-10 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        vec![ledger1, ledger2]
+    }
+
+    fn descriptions(ledger: &Ledger) -> Vec<&str> {
+        ledger
+            .transactions
+            .iter()
+            .map(|txn| txn.description.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_join_ledgers() {
+        let ledger = join_ledgers(ledgers());
+        assert_eq!(descriptions(&ledger), vec!["Rent", "Salary", "Food"]);
+        assert_eq!(ledger.commodity_prices[0].commodity_name, "USD");
+    }
+
+    #[test]
+    fn test_try_join_ledgers() {
+        assert!(try_join_ledgers(ledgers()).is_ok());
+
+        let duplicate: Ledger = "P 2020-01-01 00:00:00 USD 4.00 PLN\n".parse().unwrap();
+        let mut with_duplicate = ledgers();
+        with_duplicate.push(duplicate);
+        assert!(try_join_ledgers(with_duplicate).is_ok());
+
+        let other_target: Ledger = "P 2020-01-01 00:00:00 USD 0.90 EUR\n".parse().unwrap();
+        let mut with_other_target = ledgers();
+        with_other_target.push(other_target);
+        assert!(try_join_ledgers(with_other_target).is_ok());
+
+        let conflicting: Ledger = "P 2020-01-01 12:00:00 USD 4.10 PLN\n".parse().unwrap();
+        let mut with_conflict = ledgers();
+        with_conflict.push(conflicting);
+        let err = try_join_ledgers(with_conflict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Conflicting prices for USD on 2020-01-01: 4.00 PLN and 4.10 PLN"
+        );
+    }
+
+    #[test]
+    fn test_join_ledgers_tagged() {
+        let mut ledgers = ledgers();
+        ledgers[0].transactions[0].postings[0].tags.push(Tag {
+            name: "payday".to_string(),
+            value: None,
+        });
+        let ledger2 = ledgers.pop().unwrap();
+        let ledger1 = ledgers.pop().unwrap();
+
+        let ledger = join_ledgers_tagged(vec![
+            ("bank".to_string(), ledger1),
+            ("cash".to_string(), ledger2),
+        ]);
+
+        let salary = &ledger.transactions[1];
+        assert_eq!(salary.description, "Salary");
+        assert_eq!(salary.postings[0].tags.len(), 2);
+        assert_eq!(salary.postings[0].tags[0].name, "payday");
+        assert_eq!(
+            salary.postings[0].tags[1].value,
+            Some(TagValue::String("bank".to_string()))
+        );
+        assert_eq!(
+            ledger.transactions[0].postings[1].tags,
+            vec![Tag {
+                name: "source".to_string(),
+                value: Some(TagValue::String("cash".to_string())),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_join_ledgers_sorted() {
+        let ledger = join_ledgers_sorted(ledgers(), SortKey::DateEffectiveDescription);
+        assert_eq!(descriptions(&ledger), vec!["Rent", "Food", "Salary"]);
+        assert_eq!(ledger.commodity_prices[0].commodity_name, "EUR");
+    }
+}
@@ -1,15 +1,178 @@
 pub mod account_balance;
+pub mod aligned_serializer;
+pub mod amount_ext;
 pub mod balance;
+pub mod balance_sheet;
+pub mod beancount;
+pub mod budget;
+pub mod calculate_amounts;
+pub mod capital_gains;
+pub mod csv;
+pub mod forecast;
 pub mod handle_foreign_currencies;
+pub mod income_statement;
 pub mod join_ledgers;
 pub mod monthly_report;
+pub mod net_worth;
+pub mod period_report;
 pub mod prices;
+pub mod reconcile;
+pub mod register;
+pub mod signs;
 pub mod simplified_ledger;
 pub mod tree_balance;
 
-mod calculate_amounts;
+#[cfg(feature = "serde")]
+mod serde_support;
+#[cfg(test)]
+mod test_utils;
 
+pub use amount_ext::AmountExt;
 pub use ledger_parser::{
-    Amount, Commodity, CommodityPosition, CommodityPrice, Reality, TransactionStatus,
+    Amount, Commodity, CommodityPosition, CommodityPrice, Price, Reality, Serializer,
+    SerializerSettings, Tag, TagValue, TransactionStatus,
 };
-pub use simplified_ledger::{Error, Ledger, Posting, Transaction};
+pub use simplified_ledger::{
+    DefaultCommodity, Error, Ledger, LedgerComment, LedgerStats, Posting, Transaction,
+};
+
+/// Parses the journal and writes it back in a normalized form:
+/// amounts of all postings are filled in and metadata is written in a fixed order.
+/// Prices attached to postings (`@`, `@@`, `{}`) and balance assertions (`= AMOUNT`)
+/// are kept.
+///
+/// Only explicit `P` directives are written, not prices inferred from postings,
+/// so formatting already formatted output gives the same text.
+pub fn format(input: &str, settings: &SerializerSettings) -> Result<String, Error> {
+    let (input, directives) = simplified_ledger::take_directives(input)?;
+    let parsed: ledger_parser::Ledger = input.parse()?;
+    let commodity_prices = parsed
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            ledger_parser::LedgerItem::CommodityPrice(price) => Some(price.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut ledger = Ledger::try_from(parsed)?;
+    ledger.commodity_prices = commodity_prices;
+    directives.apply_to(&mut ledger);
+    Ok(ledger.to_string_pretty(settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS: &[&str] = &[
+        "",
+        r#"
+; header comment
+
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-01 00:00:00 USD 3.50 PLN
+
+; comment before the first transaction
+2020-01-01 * (123) Opening balance  ; opening
+  Assets:Bank  1,000.00 PLN
+  Assets:Savings  100 EUR @ 4.00 PLN
+  Assets:Broker  2 AAPL {$150} @@ 1,200 PLN
+  Equity:Opening
+
+P 2020-01-02 00:00:00 EUR 4.10 PLN
+
+2020-01-02=2020-01-05 ! Shopping
+  ; :groceries:
+  Expenses:Food  $10.5  ; [=2020-01-06]
+  ; note: weekly
+  Expenses:Food  $-0.50
+  [Budget:Food]  $-10
+  [Assets:Budget]  $10
+  (Tracking)  1 visits
+  Liabilities:Card
+
+; trailing comment
+"#,
+        r#"
+D 1,000.00 PLN
+
+2020-01-01 Opening balance
+  Assets:Bank  1000 PLN
+  Equity:Opening
+
+2020-01-03 Transfer
+  Assets:Bank  -100 PLN = 900.00 PLN
+  Assets:Cash
+
+2020-01-04 Check
+  Assets:Bank  = 900.00 PLN
+  Assets:Cash  0 PLN
+"#,
+        r#"D 1,000.00 PLN
+
+2020-01-01 Opening balance
+  Assets:Bank  1000 PLN
+  Equity:Opening  -1000 PLN
+
+2020-01-03 Transfer
+  Assets:Bank  -100 PLN = 900.00 PLN
+  Assets:Cash  100 PLN
+
+2020-01-04 Check
+  Assets:Bank  0.00 PLN = 900.00 PLN
+  Assets:Cash  0 PLN
+"#,
+    ];
+
+    #[test]
+    fn test_format_idempotence() {
+        let settings = SerializerSettings::default();
+        for input in CORPUS {
+            let formatted = format(input, &settings).unwrap();
+            assert_eq!(format(&formatted, &settings).unwrap(), formatted);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let formatted = format(CORPUS[1], &SerializerSettings::default()).unwrap();
+        assert_eq!(
+            formatted,
+            r#"; header comment
+
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-01 00:00:00 USD 3.50 PLN
+
+2020-01-01 * (123) Opening balance
+  ; comment before the first transaction
+  ; opening
+  Assets:Bank  1000.00 PLN
+  Assets:Savings  100 EUR @ 4.00 PLN
+  Assets:Broker  2 AAPL {$150} @@ 1200 PLN
+  Equity:Opening  -2600.00 PLN
+
+P 2020-01-02 00:00:00 EUR 4.10 PLN
+
+2020-01-02=2020-01-05 ! Shopping
+  ; :groceries:
+  Expenses:Food  $10.5  ; [=2020-01-06]
+  ; note: weekly
+  Expenses:Food  $-0.50
+  [Budget:Food]  $-10
+  [Assets:Budget]  $10
+  (Tracking)  1 visits
+  Liabilities:Card  $-10.00
+
+; trailing comment
+"#
+        );
+    }
+
+    #[test]
+    fn test_format_balance_assertions() {
+        let settings = SerializerSettings::default();
+        assert_eq!(format(CORPUS[2], &settings).unwrap(), CORPUS[3]);
+        assert_eq!(format(CORPUS[3], &settings).unwrap(), CORPUS[3]);
+    }
+}
@@ -1,8 +1,12 @@
-use crate::balance::Balance;
+use crate::balance::{Balance, DateMode};
+use crate::period_report::{Period, PeriodReport};
+use crate::prices::{Prices, PricesError};
 use crate::Ledger;
-use chrono::Datelike;
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MonthlyBalance {
     pub year: i32,
     pub month: u32,
@@ -22,6 +26,7 @@ impl MonthlyBalance {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MonthlyReport {
     pub monthly_balances: Vec<MonthlyBalance>,
 }
@@ -38,46 +43,216 @@ impl MonthlyReport {
             monthly_balances: Vec::new(),
         }
     }
+
+    /// Like `From<&Ledger>`, but postings are assigned to months
+    /// by the date chosen with `date_mode`.
+    pub fn from_date_mode(ledger: &Ledger, date_mode: DateMode) -> MonthlyReport {
+        PeriodReport::from_date_mode(ledger, Period::Monthly, date_mode).into()
+    }
+
+    /// Like `From<&Ledger>`, but also emits months without transactions,
+    /// up to the month that contains `end_date`.
+    pub fn from_filled(ledger: &Ledger, end_date: NaiveDate) -> MonthlyReport {
+        PeriodReport::from_filled(ledger, Period::Monthly, end_date).into()
+    }
+
+    /// Like `From<&Ledger>`, but only accounts starting with
+    /// one of `account_prefixes` are included.
+    pub fn from_filtered(ledger: &Ledger, account_prefixes: &[&str]) -> MonthlyReport {
+        PeriodReport::from_filtered(ledger, Period::Monthly, account_prefixes).into()
+    }
+
+    /// Values the total balance of every month in a single commodity,
+    /// using prices from the last day of the month.
+    pub fn value_in_commodity(
+        &self,
+        commodity_name: &str,
+        prices: &Prices,
+        decimal_points: u32,
+    ) -> Result<Vec<(i32, u32, Decimal)>, PricesError> {
+        self.monthly_balances
+            .iter()
+            .map(|b| {
+                let month_start = NaiveDate::from_ymd_opt(b.year, b.month, 1).unwrap();
+                let value = b
+                    .total
+                    .get_account_balance(&[""])
+                    .value_in_commodity_rounded(
+                        commodity_name,
+                        decimal_points,
+                        Period::Monthly.end_of(month_start),
+                        prices,
+                    )?;
+                Ok((b.year, b.month, value))
+            })
+            .collect()
+    }
+}
+
+impl From<PeriodReport> for MonthlyReport {
+    fn from(report: PeriodReport) -> Self {
+        MonthlyReport {
+            monthly_balances: report
+                .period_balances
+                .into_iter()
+                .map(|b| MonthlyBalance {
+                    year: b.start.year(),
+                    month: b.start.month(),
+                    monthly_change: b.period_change,
+                    total: b.total,
+                })
+                .collect(),
+        }
+    }
 }
 
+/// Works on the simplified `crate::Ledger`, so it composes with
+/// `TryFrom<ledger_parser::Ledger>` validation and `join_ledgers` output.
 impl<'a> From<&'a Ledger> for MonthlyReport {
     fn from(ledger: &'a Ledger) -> Self {
-        let mut report = MonthlyReport::new();
-
-        let mut current_year = 0;
-        let mut current_month = 0;
-        let mut current_monthly_balance: Option<MonthlyBalance> = None;
-        let mut monthly_balance = Balance::new();
-        let mut total_balance = Balance::new();
-
-        for transaction in &ledger.transactions {
-            if transaction.date.year() != current_year || transaction.date.month() != current_month
-            {
-                // begin new month
-
-                if let Some(mut b) = current_monthly_balance.take() {
-                    b.monthly_change = monthly_balance.clone();
-                    b.total = total_balance.clone();
-                    report.monthly_balances.push(b);
-                }
-
-                current_year = transaction.date.year();
-                current_month = transaction.date.month();
-                monthly_balance = Balance::new();
-
-                current_monthly_balance = Some(MonthlyBalance::new(current_year, current_month));
-            }
-
-            monthly_balance.update_with_transaction(transaction);
-            total_balance.update_with_transaction(transaction);
-        }
+        PeriodReport::from((ledger, Period::Monthly)).into()
+    }
+}
 
-        if let Some(mut b) = current_monthly_balance.take() {
-            b.monthly_change = monthly_balance.clone();
-            b.total = total_balance.clone();
-            report.monthly_balances.push(b);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::join_ledgers::join_ledgers;
+    use crate::test_utils::prices_from;
+
+    #[test]
+    fn test_unsorted_transactions() {
+        let ledger: Ledger = r#"
+2020-02-10 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-02-01 Food
+  Expenses:Food  $10
+  Assets:Bank
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let report: MonthlyReport = (&ledger).into();
+        assert_eq!(report.monthly_balances.len(), 2);
+
+        let january = &report.monthly_balances[0];
+        assert_eq!((january.year, january.month), (2020, 1));
+        assert_eq!(
+            january.monthly_change.account_balances["Assets:Bank"].to_string(),
+            "$95"
+        );
+
+        let february = &report.monthly_balances[1];
+        assert_eq!((february.year, february.month), (2020, 2));
+        assert_eq!(
+            february.monthly_change.account_balances["Assets:Bank"].to_string(),
+            "$-60"
+        );
+        assert_eq!(
+            february.total.account_balances["Assets:Bank"].to_string(),
+            "$35"
+        );
+    }
+
+    #[test]
+    fn test_joined_ledgers() {
+        let ledger1: Ledger = r#"
+2020-02-10 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let ledger2: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+
+        let report = MonthlyReport::from(&join_ledgers(vec![ledger1, ledger2]));
+        assert_eq!(report.monthly_balances.len(), 2);
+        assert_eq!(
+            report.monthly_balances[1].total.account_balances["Assets:Bank"].to_string(),
+            "$50"
+        );
+    }
+
+    #[test]
+    fn test_filtered() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank
+
+2020-02-10 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let report = MonthlyReport::from_filtered(&ledger, &["Expenses"]);
+        assert_eq!(report.monthly_balances.len(), 2);
+        let accounts: Vec<_> = report.monthly_balances[0]
+            .monthly_change
+            .account_balances
+            .keys()
+            .collect();
+        assert_eq!(accounts, vec!["Expenses:Food"]);
+        assert_eq!(
+            report.monthly_balances[1]
+                .total
+                .get_account_balance(&["Expenses"])
+                .to_string(),
+            "$55"
+        );
+    }
+
+    #[test]
+    fn test_value_in_commodity() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-02-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+        let prices = prices_from(
+            r#"
+P 2020-01-31 00:00:00 $ 4.00 PLN
+P 2020-02-29 00:00:00 $ 3.50 PLN
+"#,
+        );
+
+        let report = MonthlyReport::from_filtered(&ledger, &["Assets"]);
 
-        report
+        assert_eq!(
+            report.value_in_commodity("PLN", &prices, 2).unwrap(),
+            vec![
+                (2020, 1, Decimal::new(400, 0)),
+                (2020, 2, Decimal::new(700, 0))
+            ]
+        );
+        assert!(report.value_in_commodity("EUR", &prices, 2).is_err());
     }
 }
@@ -0,0 +1,90 @@
+use crate::balance::{Balance, DateMode};
+use crate::prices::{Prices, PricesError};
+use crate::Ledger;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Computes net worth (assets minus liabilities) at each of `dates`, valued in `commodity`
+/// at prices from that date.
+///
+/// Fails with the first date for which the balance couldn't be valued.
+pub fn net_worth_series(
+    ledger: &Ledger,
+    dates: &[NaiveDate],
+    asset_prefixes: &[&str],
+    liability_prefixes: &[&str],
+    commodity: &str,
+    prices: &Prices,
+) -> Result<Vec<(NaiveDate, Decimal)>, (NaiveDate, PricesError)> {
+    let mut prefixes = asset_prefixes.to_vec();
+    prefixes.extend_from_slice(liability_prefixes);
+
+    dates
+        .iter()
+        .map(|&date| {
+            // liabilities are negative, so the sum is already the net worth
+            Balance::as_of(ledger, date, DateMode::Posting)
+                .get_account_balance(&prefixes)
+                .value_in_commodity(commodity, date, prices)
+                .map(|value| (date, value))
+                .map_err(|e| (date, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+
+    #[test]
+    fn test_net_worth_series() {
+        let input = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-02-01 00:00:00 EUR 4.50 PLN
+
+2020-01-01 Opening balance
+  Assets:Bank  1000 PLN
+  Assets:Savings  100 EUR
+  Equity:Opening
+
+2020-01-10 Credit card
+  Expenses:Food  200 PLN
+  Liabilities:Card
+
+2020-02-05 Salary
+  Assets:Bank  3000 PLN
+  Income:Salary
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let prices = prices_from(input);
+
+        let series = net_worth_series(
+            &ledger,
+            &[date(2020, 1, 31), date(2020, 2, 29)],
+            &["Assets"],
+            &["Liabilities"],
+            "PLN",
+            &prices,
+        )
+        .unwrap();
+        assert_eq!(
+            series,
+            vec![
+                (date(2020, 1, 31), Decimal::new(1200, 0)),
+                (date(2020, 2, 29), Decimal::new(4250, 0)),
+            ]
+        );
+
+        let (failed_date, _) = net_worth_series(
+            &ledger,
+            &[date(2020, 1, 31), date(2020, 2, 29)],
+            &["Assets"],
+            &["Liabilities"],
+            "USD",
+            &prices,
+        )
+        .unwrap_err();
+        assert_eq!(failed_date, date(2020, 1, 31));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::balance::{Balance, DateMode};
+use crate::Ledger;
+use chrono::{Datelike, Duration, NaiveDate};
+
+/// Length of a report period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    /// Weeks starting on Monday.
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Period {
+    /// Returns the first day of the period that contains `date`.
+    pub fn start_of(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Weekly => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Monthly => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+            Period::Quarterly => {
+                NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1).unwrap()
+            }
+            Period::Yearly => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        }
+    }
+
+    /// Returns the last day of the period that contains `date`.
+    pub fn end_of(&self, date: NaiveDate) -> NaiveDate {
+        self.next_start(date) - Duration::days(1)
+    }
+
+    /// Returns the first day of the period following the one that contains `date`.
+    pub fn next_start(&self, date: NaiveDate) -> NaiveDate {
+        let start = self.start_of(date);
+        match self {
+            Period::Weekly => start + Duration::days(7),
+            Period::Monthly => add_months(start, 1),
+            Period::Quarterly => add_months(start, 3),
+            Period::Yearly => add_months(start, 12),
+        }
+    }
+}
+
+fn add_months(first_day_of_month: NaiveDate, months: u32) -> NaiveDate {
+    let months = first_day_of_month.month0() + months;
+    NaiveDate::from_ymd_opt(
+        first_day_of_month.year() + (months / 12) as i32,
+        months % 12 + 1,
+        1,
+    )
+    .unwrap()
+}
+
+#[derive(Debug, Clone)]
+pub struct PeriodBalance {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub period_change: Balance,
+    pub total: Balance,
+}
+
+impl PeriodBalance {
+    pub fn new(start: NaiveDate, end: NaiveDate) -> PeriodBalance {
+        PeriodBalance {
+            start,
+            end,
+            period_change: Balance::new(),
+            total: Balance::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeriodReport {
+    pub period: Period,
+    pub period_balances: Vec<PeriodBalance>,
+}
+
+impl PeriodReport {
+    pub fn new(period: Period) -> PeriodReport {
+        PeriodReport {
+            period,
+            period_balances: Vec::new(),
+        }
+    }
+
+    /// Like `From<(&Ledger, Period)>`, but only accounts starting with
+    /// one of `account_prefixes` are included.
+    pub fn from_filtered(
+        ledger: &Ledger,
+        period: Period,
+        account_prefixes: &[&str],
+    ) -> PeriodReport {
+        let mut report = PeriodReport::from((ledger, period));
+        let is_included = |account_name: &String| {
+            account_prefixes
+                .iter()
+                .any(|prefix| account_name.starts_with(prefix))
+        };
+        for b in report.period_balances.iter_mut() {
+            b.period_change
+                .account_balances
+                .retain(|account_name, _| is_included(account_name));
+            b.total
+                .account_balances
+                .retain(|account_name, _| is_included(account_name));
+        }
+        report
+    }
+
+    /// Like `From<(&Ledger, Period)>`, but postings are assigned to periods
+    /// by the date chosen with `date_mode` (e.g. effective dates for accrual basis).
+    pub fn from_date_mode(ledger: &Ledger, period: Period, date_mode: DateMode) -> PeriodReport {
+        let mut report = PeriodReport::new(period);
+
+        let mut current_period_balance: Option<PeriodBalance> = None;
+        let mut period_balance = Balance::new();
+        let mut total_balance = Balance::new();
+
+        // transactions don't have to be sorted
+        let mut postings: Vec<_> = ledger
+            .transactions
+            .iter()
+            .flat_map(|transaction| transaction.postings.iter())
+            .map(|posting| (date_mode.date_of(posting), posting))
+            .collect();
+        postings.sort_by_key(|(date, _)| *date);
+
+        for (date, posting) in postings {
+            let start = period.start_of(date);
+            if current_period_balance
+                .as_ref()
+                .is_none_or(|b| b.start != start)
+            {
+                // begin new period
+
+                if let Some(mut b) = current_period_balance.take() {
+                    period_balance.remove_empties();
+                    total_balance.remove_empties();
+                    b.period_change = period_balance;
+                    b.total = total_balance.clone();
+                    report.period_balances.push(b);
+                }
+
+                period_balance = Balance::new();
+
+                current_period_balance = Some(PeriodBalance::new(start, period.end_of(date)));
+            }
+
+            period_balance.add_amount(&posting.account, &posting.amount);
+            total_balance.add_amount(&posting.account, &posting.amount);
+        }
+
+        if let Some(mut b) = current_period_balance.take() {
+            period_balance.remove_empties();
+            total_balance.remove_empties();
+            b.period_change = period_balance;
+            b.total = total_balance;
+            report.period_balances.push(b);
+        }
+
+        report
+    }
+
+    /// Like `From<(&Ledger, Period)>`, but also emits periods without transactions,
+    /// up to the period that contains `end_date`.
+    /// Such periods have an empty `period_change` and the carried forward `total`.
+    pub fn from_filled(ledger: &Ledger, period: Period, end_date: NaiveDate) -> PeriodReport {
+        let sparse = PeriodReport::from((ledger, period));
+        let mut report = PeriodReport::new(period);
+
+        let mut sparse_balances = sparse.period_balances.into_iter().peekable();
+        let mut start = match sparse_balances.peek() {
+            Some(b) => b.start,
+            None => return report,
+        };
+        let mut total = Balance::new();
+
+        while start <= end_date || sparse_balances.peek().is_some() {
+            match sparse_balances.next_if(|b| b.start == start) {
+                Some(b) => {
+                    total = b.total.clone();
+                    report.period_balances.push(b);
+                }
+                None => {
+                    let mut b = PeriodBalance::new(start, period.end_of(start));
+                    b.total = total.clone();
+                    report.period_balances.push(b);
+                }
+            }
+            start = period.next_start(start);
+        }
+
+        report
+    }
+}
+
+impl<'a> From<(&'a Ledger, Period)> for PeriodReport {
+    fn from((ledger, period): (&'a Ledger, Period)) -> Self {
+        let mut report = PeriodReport::new(period);
+
+        let mut current_period_balance: Option<PeriodBalance> = None;
+        let mut period_balance = Balance::new();
+        let mut total_balance = Balance::new();
+
+        // transactions don't have to be sorted
+        let mut transactions: Vec<_> = ledger.transactions.iter().collect();
+        transactions.sort_by_key(|transaction| transaction.date);
+
+        for transaction in transactions {
+            let start = period.start_of(transaction.date);
+            if current_period_balance
+                .as_ref()
+                .is_none_or(|b| b.start != start)
+            {
+                // begin new period
+
+                if let Some(mut b) = current_period_balance.take() {
+                    b.period_change = period_balance.clone();
+                    b.total = total_balance.clone();
+                    report.period_balances.push(b);
+                }
+
+                period_balance = Balance::new();
+
+                current_period_balance =
+                    Some(PeriodBalance::new(start, period.end_of(transaction.date)));
+            }
+
+            period_balance.update_with_transaction(transaction);
+            total_balance.update_with_transaction(transaction);
+        }
+
+        if let Some(mut b) = current_period_balance.take() {
+            b.period_change = period_balance.clone();
+            b.total = total_balance.clone();
+            report.period_balances.push(b);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::date;
+
+    #[test]
+    fn test_period_bounds() {
+        // 2020-08-13 is a Thursday
+        let d = date(2020, 8, 13);
+        assert_eq!(Period::Weekly.start_of(d), date(2020, 8, 10));
+        assert_eq!(Period::Weekly.end_of(d), date(2020, 8, 16));
+        assert_eq!(Period::Monthly.start_of(d), date(2020, 8, 1));
+        assert_eq!(Period::Monthly.end_of(d), date(2020, 8, 31));
+        assert_eq!(Period::Quarterly.start_of(d), date(2020, 7, 1));
+        assert_eq!(Period::Quarterly.end_of(d), date(2020, 9, 30));
+        assert_eq!(Period::Yearly.start_of(d), date(2020, 1, 1));
+        assert_eq!(Period::Yearly.end_of(d), date(2020, 12, 31));
+        assert_eq!(
+            Period::Quarterly.end_of(date(2020, 11, 5)),
+            date(2020, 12, 31)
+        );
+    }
+
+    #[test]
+    fn test_filled_report() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-04-15 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let report = PeriodReport::from_filled(&ledger, Period::Monthly, date(2020, 6, 10));
+        let starts: Vec<_> = report.period_balances.iter().map(|b| b.start).collect();
+        assert_eq!(
+            starts,
+            vec![
+                date(2020, 1, 1),
+                date(2020, 2, 1),
+                date(2020, 3, 1),
+                date(2020, 4, 1),
+                date(2020, 5, 1),
+                date(2020, 6, 1),
+            ]
+        );
+
+        let march = &report.period_balances[2];
+        assert!(march.period_change.account_balances.is_empty());
+        assert_eq!(
+            march.total.account_balances["Assets:Bank"].to_string(),
+            "$100"
+        );
+        let june = &report.period_balances[5];
+        assert!(june.period_change.account_balances.is_empty());
+        assert_eq!(
+            june.total.account_balances["Assets:Bank"].to_string(),
+            "$50"
+        );
+    }
+
+    #[test]
+    fn test_transaction_dates() {
+        let ledger: Ledger = r#"
+2020-01-28 Insurance
+  Expenses:Insurance  $120
+  Assets:Bank  ; [2020-02-03]
+"#
+        .parse()
+        .unwrap();
+
+        let report = PeriodReport::from((&ledger, Period::Monthly));
+        assert_eq!(report.period_balances.len(), 1);
+        assert_eq!(report.period_balances[0].start, date(2020, 1, 1));
+
+        let cash = PeriodReport::from_date_mode(&ledger, Period::Monthly, DateMode::Posting);
+        let starts: Vec<_> = cash.period_balances.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![date(2020, 1, 1), date(2020, 2, 1)]);
+    }
+
+    #[test]
+    fn test_date_mode() {
+        let ledger: Ledger = r#"
+2020-01-28 Insurance
+  Expenses:Insurance  $120  ; [=2020-02-01]
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let cash = PeriodReport::from_date_mode(&ledger, Period::Monthly, DateMode::Posting);
+        assert_eq!(cash.period_balances.len(), 1);
+        assert_eq!(cash.period_balances[0].start, date(2020, 1, 1));
+
+        let accrual = PeriodReport::from_date_mode(&ledger, Period::Monthly, DateMode::Effective);
+        let starts: Vec<_> = accrual.period_balances.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![date(2020, 1, 1), date(2020, 2, 1)]);
+        assert_eq!(
+            accrual.period_balances[0].period_change.account_balances["Assets:Bank"].to_string(),
+            "$-120"
+        );
+        assert!(!accrual.period_balances[0]
+            .total
+            .account_balances
+            .contains_key("Expenses:Insurance"));
+        assert_eq!(
+            accrual.period_balances[1].total.account_balances["Expenses:Insurance"].to_string(),
+            "$120"
+        );
+    }
+
+    #[test]
+    fn test_quarterly_report() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-03-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-05-15 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let report: PeriodReport = (&ledger, Period::Quarterly).into();
+        assert_eq!(report.period_balances.len(), 2);
+
+        let q1 = &report.period_balances[0];
+        assert_eq!(q1.start, date(2020, 1, 1));
+        assert_eq!(q1.end, date(2020, 3, 31));
+        assert_eq!(
+            q1.period_change.account_balances["Assets:Bank"].to_string(),
+            "$200"
+        );
+
+        let q2 = &report.period_balances[1];
+        assert_eq!(q2.start, date(2020, 4, 1));
+        assert_eq!(
+            q2.period_change.account_balances["Assets:Bank"].to_string(),
+            "$-50"
+        );
+        assert_eq!(q2.total.account_balances["Assets:Bank"].to_string(), "$150");
+    }
+}
@@ -1,13 +1,62 @@
-use chrono::NaiveDate;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
 use ledger_parser::*;
 use rust_decimal::Decimal;
-use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub enum PricesError {
     NoSuchCommoditiesPair(CommoditiesPair),
     DateTooEarly(NaiveDate),
+    InvalidCsvRow {
+        line: usize,
+        message: String,
+    },
+    StalePrice {
+        pair: CommoditiesPair,
+        last_date: NaiveDate,
+    },
+    Io(io::Error),
+}
+
+impl std::error::Error for PricesError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PricesError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PricesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PricesError::NoSuchCommoditiesPair(pair) => {
+                write!(f, "No price for {}", pair)
+            }
+            PricesError::DateTooEarly(date) => {
+                write!(f, "No rate available on or before {}", date)
+            }
+            PricesError::InvalidCsvRow { line, message } => {
+                write!(f, "Invalid CSV row at line {}: {}", line, message)
+            }
+            PricesError::Io(e) => {
+                write!(f, "I/O error: {}", e)
+            }
+            PricesError::StalePrice { pair, last_date } => {
+                write!(f, "Stale price for {}, last rate from {}", pair, last_date)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for PricesError {
+    fn from(e: io::Error) -> Self {
+        PricesError::Io(e)
+    }
 }
 
 #[derive(Eq, PartialEq, Hash, Clone, Debug)]
@@ -16,6 +65,16 @@ pub struct CommoditiesPair {
     pub dst_commodity_name: String,
 }
 
+impl fmt::Display for CommoditiesPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}->{}",
+            self.src_commodity_name, self.dst_commodity_name
+        )
+    }
+}
+
 impl CommoditiesPair {
     pub fn new(src_commodity_name: &str, dst_commodity_name: &str) -> CommoditiesPair {
         CommoditiesPair {
@@ -25,28 +84,134 @@ impl CommoditiesPair {
     }
 }
 
+/// Method used to look up a rate between known price dates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLookup {
+    /// Use the last known rate (forward-fill).
+    #[default]
+    Step,
+    /// Interpolate linearly between the surrounding known rates.
+    Interpolated,
+}
+
+/// Decides which rate wins when two merged `Prices` have a rate
+/// for the same commodities pair on the same date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    PreferSelf,
+    PreferOther,
+    /// Prefer the table that has more recent prices for the given commodities pair.
+    /// On a tie the other table wins.
+    PreferNewer,
+}
+
+/// Rates of a commodities pair by date.
+///
+/// When there are several prices on the same day, the one with the latest time wins
+/// (on equal times the one added last wins). Lookups by date see the last rate
+/// at or before the end of that day.
 #[derive(Debug)]
 pub struct RatesTable {
     pub table: BTreeMap<NaiveDate, Decimal>,
+    times: HashMap<NaiveDate, NaiveTime>,
+    /// Dates of rates that were generated as inverses of other rates.
+    inverses: HashSet<NaiveDate>,
 }
 
 impl RatesTable {
     fn new() -> RatesTable {
         RatesTable {
             table: BTreeMap::new(),
+            times: HashMap::new(),
+            inverses: HashSet::new(),
         }
     }
 
-    fn get_rate(&self, date: NaiveDate) -> Result<Decimal, PricesError> {
-        let mut rate: Option<Decimal> = None;
-        for (key, value) in self.table.iter() {
-            if *key <= date {
-                rate = Some(*value)
-            } else {
-                break;
+    /// Inserts the rate unless there is already a rate with a later time on the same day.
+    fn insert(&mut self, datetime: NaiveDateTime, rate: Decimal, is_inverse: bool) {
+        let date = datetime.date();
+        if self
+            .times
+            .get(&date)
+            .is_some_and(|time| *time > datetime.time())
+        {
+            return;
+        }
+        self.set(datetime, rate, is_inverse);
+    }
+
+    fn set(&mut self, datetime: NaiveDateTime, rate: Decimal, is_inverse: bool) {
+        let date = datetime.date();
+        self.table.insert(date, rate);
+        self.times.insert(date, datetime.time());
+        if is_inverse {
+            self.inverses.insert(date);
+        } else {
+            self.inverses.remove(&date);
+        }
+    }
+
+    pub fn get_rate(&self, date: NaiveDate) -> Result<Decimal, PricesError> {
+        self.get_entry(date).map(|(_, rate)| rate)
+    }
+
+    /// Returns the rate interpolated linearly (by days) between the surrounding known rates.
+    /// Dates after the last known rate are clamped to that rate.
+    pub fn get_rate_interpolated(&self, date: NaiveDate) -> Result<Decimal, PricesError> {
+        let (before_date, before_rate) = self.get_entry(date)?;
+        if before_date == date {
+            return Ok(before_rate);
+        }
+
+        match self.table.range(date..).next() {
+            Some((after_date, after_rate)) => {
+                let total_days = Decimal::from((*after_date - before_date).num_days());
+                let days = Decimal::from((date - before_date).num_days());
+                Ok(before_rate + (*after_rate - before_rate) * days / total_days)
             }
+            None => Ok(before_rate),
+        }
+    }
+
+    pub fn get_rate_with(
+        &self,
+        date: NaiveDate,
+        lookup: RateLookup,
+    ) -> Result<Decimal, PricesError> {
+        match lookup {
+            RateLookup::Step => self.get_rate(date),
+            RateLookup::Interpolated => self.get_rate_interpolated(date),
         }
-        rate.ok_or(PricesError::DateTooEarly(date))
+    }
+
+    pub fn first_date(&self) -> Option<NaiveDate> {
+        self.table.keys().next().copied()
+    }
+
+    pub fn last_date(&self) -> Option<NaiveDate> {
+        self.table.keys().next_back().copied()
+    }
+
+    /// Returns the first and the last date with a known rate.
+    pub fn date_range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        Some((self.first_date()?, self.last_date()?))
+    }
+
+    /// Returns the newest entry (date and rate).
+    pub fn latest_entry(&self) -> Option<(NaiveDate, Decimal)> {
+        self.table
+            .iter()
+            .next_back()
+            .map(|(key, value)| (*key, *value))
+    }
+
+    /// Returns the last entry (date and rate) whose date is not later than `date`.
+    fn get_entry(&self, date: NaiveDate) -> Result<(NaiveDate, Decimal), PricesError> {
+        self.table
+            .range(..=date)
+            .next_back()
+            .map(|(key, value)| (*key, *value))
+            .ok_or(PricesError::DateTooEarly(date))
     }
 }
 
@@ -69,8 +234,201 @@ impl Prices {
     }
 
     pub fn insert_from(&mut self, ledger: &Ledger) {
+        self.insert_from_with(ledger, &|_| false);
+    }
+
+    /// Like `insert_from`, but postings to fee accounts are ignored
+    /// when inferring prices from transactions.
+    pub fn insert_from_with<F>(&mut self, ledger: &Ledger, is_fee_account: &F)
+    where
+        F: Fn(&str) -> bool,
+    {
         self.add_prices(&get_commodity_prices(ledger));
-        self.add_prices(&get_prices_from_transactions(ledger));
+        self.add_prices(&get_prices_from_transactions(ledger, is_fee_account));
+    }
+
+    /// Like `insert_from` into empty `Prices`, but prices are extracted from
+    /// the ledger items in parallel. The result is the same.
+    #[cfg(feature = "rayon")]
+    pub fn load_parallel(ledger: &Ledger) -> Self {
+        use rayon::prelude::*;
+
+        let (commodity_prices, transaction_prices) = rayon::join(
+            || {
+                ledger
+                    .items
+                    .par_iter()
+                    .filter_map(|item| match item {
+                        LedgerItem::CommodityPrice(commodity_price) => {
+                            Some(commodity_price.clone())
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            },
+            || {
+                ledger
+                    .items
+                    .par_iter()
+                    .flat_map_iter(|item| match item {
+                        LedgerItem::Transaction(transaction) => {
+                            get_prices_from_transaction(transaction, &|_| false)
+                        }
+                        _ => Vec::new(),
+                    })
+                    .collect::<Vec<_>>()
+            },
+        );
+
+        // A price only affects rates tables of its pair of commodities (in both directions),
+        // so pairs can be built independently. Prices of a pair keep their order.
+        let mut prices_by_pair: HashMap<(String, String), Vec<CommodityPrice>> = HashMap::new();
+        for price in commodity_prices.into_iter().chain(transaction_prices) {
+            let mut key = (
+                price.commodity_name.clone(),
+                price.amount.commodity.name.clone(),
+            );
+            if key.0 > key.1 {
+                key = (key.1, key.0);
+            }
+            prices_by_pair.entry(key).or_default().push(price);
+        }
+
+        let rates = prices_by_pair
+            .into_par_iter()
+            .map(|(_, pair_prices)| {
+                let mut prices = Prices::new();
+                prices.add_prices(&pair_prices);
+                prices.rates
+            })
+            .reduce(HashMap::new, |mut rates, pair_rates| {
+                rates.extend(pair_rates);
+                rates
+            });
+
+        Prices { rates }
+    }
+
+    /// Returns all commodities that appear in any commodities pair.
+    pub fn commodities(&self) -> BTreeSet<String> {
+        self.rates
+            .keys()
+            .flat_map(|pair| {
+                [
+                    pair.src_commodity_name.clone(),
+                    pair.dst_commodity_name.clone(),
+                ]
+            })
+            .collect()
+    }
+
+    /// Returns all commodities pairs, sorted by names.
+    pub fn pairs(&self) -> Vec<CommoditiesPair> {
+        let mut pairs: Vec<CommoditiesPair> = self.rates.keys().cloned().collect();
+        pairs.sort_by(|a, b| {
+            a.src_commodity_name
+                .cmp(&b.src_commodity_name)
+                .then_with(|| a.dst_commodity_name.cmp(&b.dst_commodity_name))
+        });
+        pairs
+    }
+
+    /// Loads rates from a CSV file with the columns: date, src_commodity, dst_commodity, rate.
+    ///
+    /// Dates use the `%Y-%m-%d` format. Empty lines and an optional header
+    /// (first line starting with `date`) are skipped. Inverse rates are added as well.
+    pub fn load_csv<R: Read>(&mut self, reader: R) -> Result<(), PricesError> {
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line_number = index + 1;
+
+            if line.trim().is_empty()
+                || line_number == 1 && line.trim_start().to_lowercase().starts_with("date")
+            {
+                continue;
+            }
+
+            let (src_commodity_name, dst_commodity_name, rate, date) = parse_csv_row(&line)
+                .map_err(|message| PricesError::InvalidCsvRow {
+                    line: line_number,
+                    message,
+                })?;
+            self.add_rate(
+                src_commodity_name,
+                dst_commodity_name,
+                rate,
+                date.and_time(NaiveTime::MIN),
+            );
+        }
+        Ok(())
+    }
+
+    /// Merges rates from `other`. Conflicting rates are resolved with `prefer`.
+    pub fn merge(&mut self, other: Prices, prefer: MergePolicy) {
+        for (commodities_pair, other_rates_table) in other.rates {
+            let rates_table = self
+                .rates
+                .entry(commodities_pair)
+                .or_insert_with(RatesTable::new);
+
+            let prefer_other = match prefer {
+                MergePolicy::PreferSelf => false,
+                MergePolicy::PreferOther => true,
+                MergePolicy::PreferNewer => {
+                    other_rates_table.last_date() >= rates_table.last_date()
+                }
+            };
+
+            for (date, rate) in other_rates_table.table {
+                if prefer_other || !rates_table.table.contains_key(&date) {
+                    let time = other_rates_table
+                        .times
+                        .get(&date)
+                        .copied()
+                        .unwrap_or(NaiveTime::MIN);
+                    let is_inverse = other_rates_table.inverses.contains(&date);
+                    rates_table.set(date.and_time(time), rate, is_inverse);
+                }
+            }
+        }
+    }
+
+    /// Exports rates as commodity price directives, one per commodities pair and date.
+    /// Automatically generated inverse rates are skipped.
+    pub fn to_commodity_prices(&self) -> Vec<CommodityPrice> {
+        let mut result = Vec::new();
+        for (commodities_pair, rates_table) in &self.rates {
+            for (date, rate) in &rates_table.table {
+                if rates_table.inverses.contains(date) {
+                    continue;
+                }
+
+                let time = rates_table
+                    .times
+                    .get(date)
+                    .copied()
+                    .unwrap_or(NaiveTime::MIN);
+                result.push(CommodityPrice {
+                    datetime: date.and_time(time),
+                    commodity_name: commodities_pair.src_commodity_name.clone(),
+                    amount: Amount {
+                        quantity: *rate,
+                        commodity: Commodity {
+                            name: commodities_pair.dst_commodity_name.clone(),
+                            position: CommodityPosition::Right,
+                        },
+                    },
+                });
+            }
+        }
+
+        result.sort_by(|a, b| {
+            a.datetime
+                .cmp(&b.datetime)
+                .then_with(|| a.commodity_name.cmp(&b.commodity_name))
+                .then_with(|| a.amount.commodity.name.cmp(&b.amount.commodity.name))
+        });
+        result
     }
 
     pub fn convert(
@@ -84,15 +442,191 @@ impl Prices {
         Ok(amount * rate)
     }
 
+    /// Converts the amount to `dst_commodity_name`.
+    /// The resulting commodity is placed at `position`.
+    pub fn convert_amount(
+        &self,
+        amount: &Amount,
+        dst_commodity_name: &str,
+        position: CommodityPosition,
+        date: NaiveDate,
+    ) -> Result<Amount, PricesError> {
+        let quantity = if amount.commodity.name == dst_commodity_name {
+            amount.quantity
+        } else {
+            self.convert(
+                amount.quantity,
+                &amount.commodity.name,
+                dst_commodity_name,
+                date,
+            )?
+        };
+
+        Ok(Amount {
+            quantity,
+            commodity: Commodity {
+                name: dst_commodity_name.to_string(),
+                position,
+            },
+        })
+    }
+
     pub fn get_rate(
         &self,
         src_commodity_name: &str,
         dst_commodity_name: &str,
         date: NaiveDate,
     ) -> Result<Decimal, PricesError> {
+        self.get_rate_through(src_commodity_name, dst_commodity_name, date, None)
+    }
+
+    /// Converts the amount using the most recent rate (regardless of date).
+    /// Returns the date of the used rate and the converted amount.
+    pub fn convert_latest(
+        &self,
+        amount: Decimal,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+    ) -> Result<(NaiveDate, Decimal), PricesError> {
+        let (date, rate) = self.latest_rate(src_commodity_name, dst_commodity_name)?;
+        Ok((date, amount * rate))
+    }
+
+    /// Gets the most recent rate (regardless of date) together with its date.
+    pub fn latest_rate(
+        &self,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+    ) -> Result<(NaiveDate, Decimal), PricesError> {
         let commodities_pair = CommoditiesPair::new(src_commodity_name, dst_commodity_name);
 
-        self.get_rates_table(&commodities_pair)?.get_rate(date)
+        self.get_rates_table(&commodities_pair)?
+            .latest_entry()
+            .ok_or(PricesError::NoSuchCommoditiesPair(commodities_pair))
+    }
+
+    /// Gets the rate between two commodities.
+    ///
+    /// If there is no direct rate, the rate is composed from two rates
+    /// through an intermediate commodity (at most one hop).
+    /// `preferred_commodity` (usually the main commodity) is tried first,
+    /// otherwise the intermediate commodity with the price date closest to `date` is used.
+    pub fn get_rate_through(
+        &self,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+        date: NaiveDate,
+        preferred_commodity: Option<&str>,
+    ) -> Result<Decimal, PricesError> {
+        self.get_entry_through(
+            src_commodity_name,
+            dst_commodity_name,
+            date,
+            preferred_commodity,
+        )
+        .map(|(_, rate)| rate)
+    }
+
+    /// Gets the rate between two commodities like `get_rate`, but fails with
+    /// `PricesError::StalePrice` if the applicable price is older than `date - max_age`.
+    pub fn get_rate_fresh(
+        &self,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+        date: NaiveDate,
+        max_age: Duration,
+    ) -> Result<Decimal, PricesError> {
+        let (last_date, rate) =
+            self.get_entry_through(src_commodity_name, dst_commodity_name, date, None)?;
+        if last_date < date - max_age {
+            return Err(PricesError::StalePrice {
+                pair: CommoditiesPair::new(src_commodity_name, dst_commodity_name),
+                last_date,
+            });
+        }
+        Ok(rate)
+    }
+
+    /// Returns the applicable price date together with the rate.
+    /// For composed rates the older of the two price dates is returned.
+    fn get_entry_through(
+        &self,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+        date: NaiveDate,
+        preferred_commodity: Option<&str>,
+    ) -> Result<(NaiveDate, Decimal), PricesError> {
+        let commodities_pair = CommoditiesPair::new(src_commodity_name, dst_commodity_name);
+
+        if let Some(rates_table) = self.rates.get(&commodities_pair) {
+            return rates_table.get_entry(date);
+        }
+
+        if let Some(preferred_commodity) = preferred_commodity {
+            if let Some(entry) = self.get_indirect_entry(
+                src_commodity_name,
+                preferred_commodity,
+                dst_commodity_name,
+                date,
+            ) {
+                return Ok(entry);
+            }
+        }
+
+        let mut best: Option<(NaiveDate, &str, Decimal)> = None;
+        for pair in self.rates.keys() {
+            if pair.src_commodity_name != src_commodity_name
+                || pair.dst_commodity_name == dst_commodity_name
+            {
+                continue;
+            }
+
+            let intermediate = pair.dst_commodity_name.as_str();
+            if let Some((entry_date, rate)) =
+                self.get_indirect_entry(src_commodity_name, intermediate, dst_commodity_name, date)
+            {
+                // prefer the newest price, then the commodity name to stay deterministic
+                let is_better = match best {
+                    None => true,
+                    Some((best_date, best_intermediate, _)) => {
+                        entry_date > best_date
+                            || entry_date == best_date && intermediate < best_intermediate
+                    }
+                };
+                if is_better {
+                    best = Some((entry_date, intermediate, rate));
+                }
+            }
+        }
+
+        best.map(|(entry_date, _, rate)| (entry_date, rate))
+            .ok_or(PricesError::NoSuchCommoditiesPair(commodities_pair))
+    }
+
+    /// Composes the rate `src -> intermediate -> dst`.
+    /// Returns the older of the two price dates together with the rate.
+    fn get_indirect_entry(
+        &self,
+        src_commodity_name: &str,
+        intermediate_commodity_name: &str,
+        dst_commodity_name: &str,
+        date: NaiveDate,
+    ) -> Option<(NaiveDate, Decimal)> {
+        let first = self
+            .get_rates_table(&CommoditiesPair::new(
+                src_commodity_name,
+                intermediate_commodity_name,
+            ))
+            .and_then(|rates_table| rates_table.get_entry(date))
+            .ok()?;
+        let second = self
+            .get_rates_table(&CommoditiesPair::new(
+                intermediate_commodity_name,
+                dst_commodity_name,
+            ))
+            .and_then(|rates_table| rates_table.get_entry(date))
+            .ok()?;
+        Some((first.0.min(second.0), first.1 * second.1))
     }
 
     fn get_rates_table(
@@ -106,17 +640,38 @@ impl Prices {
 
     fn add_prices(&mut self, prices: &[CommodityPrice]) {
         for price in prices {
-            self.add_price(
+            self.add_rate(
                 &price.commodity_name,
                 &price.amount.commodity.name,
                 price.amount.quantity,
-                price.datetime.date(),
+                price.datetime,
             );
+        }
+    }
+
+    /// Adds the rate together with its inverse.
+    /// The inverse of a zero rate is skipped.
+    fn add_rate(
+        &mut self,
+        src_commodity_name: &str,
+        dst_commodity_name: &str,
+        rate: Decimal,
+        datetime: NaiveDateTime,
+    ) {
+        self.add_price(
+            src_commodity_name,
+            dst_commodity_name,
+            rate,
+            datetime,
+            false,
+        );
+        if !rate.is_zero() {
             self.add_price(
-                &price.amount.commodity.name,
-                &price.commodity_name,
-                Decimal::new(1, 0) / price.amount.quantity,
-                price.datetime.date(),
+                dst_commodity_name,
+                src_commodity_name,
+                Decimal::new(1, 0) / rate,
+                datetime,
+                true,
             );
         }
     }
@@ -126,17 +681,32 @@ impl Prices {
         src_commodity_name: &str,
         dst_commodity_name: &str,
         rate: Decimal,
-        date: NaiveDate,
+        datetime: NaiveDateTime,
+        is_inverse: bool,
     ) {
         let commodities_pair = CommoditiesPair::new(src_commodity_name, dst_commodity_name);
         self.rates
             .entry(commodities_pair)
             .or_insert_with(RatesTable::new)
-            .table
-            .entry(date)
-            .and_modify(|r| *r = rate)
-            .or_insert(rate);
+            .insert(datetime, rate, is_inverse);
+    }
+}
+
+fn parse_csv_row(line: &str) -> Result<(&str, &str, Decimal, NaiveDate), String> {
+    let columns: Vec<&str> = line.split(',').map(|column| column.trim()).collect();
+    if columns.len() != 4 {
+        return Err(format!("expected 4 columns, found {}", columns.len()));
+    }
+
+    let date = NaiveDate::parse_from_str(columns[0], "%Y-%m-%d")
+        .map_err(|e| format!("invalid date '{}': {}", columns[0], e))?;
+    if columns[1].is_empty() || columns[2].is_empty() {
+        return Err("empty commodity name".to_string());
     }
+    let rate = Decimal::from_str(columns[3])
+        .map_err(|e| format!("invalid rate '{}': {}", columns[3], e))?;
+
+    Ok((columns[1], columns[2], rate, date))
 }
 
 fn get_commodity_prices(ledger: &Ledger) -> Vec<CommodityPrice> {
@@ -149,73 +719,543 @@ fn get_commodity_prices(ledger: &Ledger) -> Vec<CommodityPrice> {
     result
 }
 
-fn get_prices_from_transactions(ledger: &Ledger) -> Vec<CommodityPrice> {
+/// Infers prices from transactions.
+///
+/// Explicit posting prices (`@`, `@@` or lot prices) are used when present.
+/// Otherwise the price is derived from postings (other than fees)
+/// that net to non-zero amounts in exactly two commodities.
+fn get_prices_from_transactions<F>(ledger: &Ledger, is_fee_account: &F) -> Vec<CommodityPrice>
+where
+    F: Fn(&str) -> bool,
+{
     let mut result = Vec::new();
     for item in &ledger.items {
         if let LedgerItem::Transaction(transaction) = item {
-            // TODO: handle empty amounts & balance verifications
-            if transaction.postings.len() == 2
-                && transaction.postings[0]
-                    .amount
-                    .clone()
-                    .unwrap()
-                    .amount
-                    .commodity
-                    .name
-                    != transaction.postings[1]
-                        .amount
-                        .clone()
-                        .unwrap()
-                        .amount
-                        .commodity
-                        .name
-                && transaction.postings[0]
-                    .amount
-                    .clone()
-                    .unwrap()
-                    .amount
-                    .quantity
-                    != Decimal::new(0, 0)
-                && transaction.postings[1]
-                    .amount
-                    .clone()
-                    .unwrap()
-                    .amount
-                    .quantity
-                    != Decimal::new(0, 0)
-            {
-                result.push(CommodityPrice {
-                    datetime: transaction.date.and_hms_opt(0, 0, 0).unwrap(),
-                    commodity_name: (transaction.postings[0])
-                        .amount
-                        .clone()
-                        .unwrap()
-                        .amount
-                        .commodity
-                        .name,
-                    amount: Amount {
-                        quantity: -transaction.postings[1]
-                            .amount
-                            .clone()
-                            .unwrap()
-                            .amount
-                            .quantity
-                            / transaction.postings[0]
-                                .amount
-                                .clone()
-                                .unwrap()
-                                .amount
-                                .quantity,
-                        commodity: (transaction.postings[1])
-                            .amount
-                            .clone()
-                            .unwrap()
-                            .amount
-                            .commodity,
-                    },
-                })
-            }
+            result.extend(get_prices_from_transaction(transaction, is_fee_account));
         }
     }
     result
 }
+
+fn get_prices_from_transaction<F>(
+    transaction: &Transaction,
+    is_fee_account: &F,
+) -> Vec<CommodityPrice>
+where
+    F: Fn(&str) -> bool,
+{
+    let explicit_prices: Vec<CommodityPrice> = transaction
+        .postings
+        .iter()
+        .filter(|posting| !is_fee_account(&posting.account))
+        .filter_map(|posting| get_explicit_price(transaction.date, posting))
+        .collect();
+    if !explicit_prices.is_empty() {
+        return explicit_prices;
+    }
+
+    // TODO: handle empty amounts & balance verifications
+    let mut totals: Vec<Amount> = Vec::new();
+    for posting in &transaction.postings {
+        if is_fee_account(&posting.account) {
+            continue;
+        }
+
+        match &posting.amount {
+            Some(posting_amount) => {
+                let amount = &posting_amount.amount;
+                match totals
+                    .iter_mut()
+                    .find(|total| total.commodity.name == amount.commodity.name)
+                {
+                    Some(total) => total.quantity += amount.quantity,
+                    None => totals.push(amount.clone()),
+                }
+            }
+            None => return Vec::new(),
+        }
+    }
+
+    totals.retain(|total| !total.quantity.is_zero());
+    if totals.len() != 2 {
+        return Vec::new();
+    }
+
+    vec![CommodityPrice {
+        datetime: transaction.date.and_hms_opt(0, 0, 0).unwrap(),
+        commodity_name: totals[0].commodity.name.clone(),
+        amount: Amount {
+            quantity: -totals[1].quantity / totals[0].quantity,
+            commodity: totals[1].commodity.clone(),
+        },
+    }]
+}
+
+fn get_explicit_price(date: NaiveDate, posting: &Posting) -> Option<CommodityPrice> {
+    let posting_amount = posting.amount.as_ref()?;
+    let amount = &posting_amount.amount;
+    let price = posting_amount
+        .price
+        .as_ref()
+        .or(posting_amount.lot_price.as_ref())?;
+
+    let unit_price = match price {
+        Price::Unit(unit_price) => unit_price.clone(),
+        Price::Total(total_price) => {
+            if amount.quantity.is_zero() {
+                return None;
+            }
+            Amount {
+                quantity: total_price.quantity / amount.quantity.abs(),
+                commodity: total_price.commodity.clone(),
+            }
+        }
+    };
+
+    if unit_price.commodity.name == amount.commodity.name {
+        return None;
+    }
+
+    Some(CommodityPrice {
+        datetime: date.and_hms_opt(0, 0, 0).unwrap(),
+        commodity_name: amount.commodity.name.clone(),
+        amount: unit_price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+
+    #[test]
+    fn test_convert_through_intermediate_commodity() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 ADA $0.05
+P 2020-01-01 00:00:00 $ 4.00 PLN
+"#,
+        );
+        assert_eq!(
+            prices
+                .convert(Decimal::new(100, 0), "ADA", "PLN", date(2020, 2, 1))
+                .unwrap(),
+            Decimal::new(20, 0)
+        );
+        assert_eq!(
+            prices
+                .convert(Decimal::new(20, 0), "PLN", "ADA", date(2020, 2, 1))
+                .unwrap(),
+            Decimal::new(100, 0)
+        );
+    }
+
+    #[test]
+    fn test_get_rate_through_picks_newest_intermediate() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 ADA $0.05
+P 2020-01-01 00:00:00 $ 4.00 PLN
+P 2020-03-01 00:00:00 ADA 0.04 EUR
+P 2020-03-01 00:00:00 EUR 6.00 PLN
+"#,
+        );
+        assert_eq!(
+            prices.get_rate("ADA", "PLN", date(2020, 3, 2)).unwrap(),
+            Decimal::new(24, 2)
+        );
+        assert_eq!(
+            prices
+                .get_rate_through("ADA", "PLN", date(2020, 3, 2), Some("$"))
+                .unwrap(),
+            Decimal::new(20, 2)
+        );
+        assert_eq!(
+            prices.get_rate("ADA", "PLN", date(2020, 2, 1)).unwrap(),
+            Decimal::new(20, 2)
+        );
+    }
+
+    #[test]
+    fn test_get_rate_interpolated() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-11 00:00:00 EUR 5.00 PLN
+"#,
+        );
+        let rates_table = &prices.rates[&CommoditiesPair::new("EUR", "PLN")];
+
+        assert!(matches!(
+            rates_table.get_rate_interpolated(date(2019, 12, 31)),
+            Err(PricesError::DateTooEarly(_))
+        ));
+        assert_eq!(
+            rates_table.get_rate_interpolated(date(2020, 1, 1)).unwrap(),
+            Decimal::new(4, 0)
+        );
+        assert_eq!(
+            rates_table.get_rate_interpolated(date(2020, 1, 4)).unwrap(),
+            Decimal::new(43, 1)
+        );
+        assert_eq!(
+            rates_table.get_rate_interpolated(date(2020, 2, 1)).unwrap(),
+            Decimal::new(5, 0)
+        );
+        assert_eq!(
+            rates_table
+                .get_rate_with(date(2020, 1, 4), RateLookup::Step)
+                .unwrap(),
+            Decimal::new(4, 0)
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_load_parallel() {
+        let input = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-01 00:00:00 PLN 0.26 EUR
+P 2020-01-02 00:00:00 USD 3.50 PLN
+
+2020-01-01 Exchange
+  Assets:EUR  10 EUR
+  Assets:PLN  -41 PLN
+
+2020-01-03 Buy
+  Assets:AAPL  2 AAPL @ $100
+  Assets:USD
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let sequential = prices_from(input);
+        let parallel = Prices::load_parallel(&ledger);
+
+        assert_eq!(parallel.pairs(), sequential.pairs());
+        for (pair, rates_table) in &sequential.rates {
+            assert_eq!(parallel.rates[pair].table, rates_table.table);
+        }
+    }
+
+    #[test]
+    fn test_latest_rate_and_date_range() {
+        let prices = prices_from(
+            r#"
+P 2020-01-11 00:00:00 EUR 5.00 PLN
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+"#,
+        );
+        assert_eq!(
+            prices.latest_rate("EUR", "PLN").unwrap(),
+            (date(2020, 1, 11), Decimal::new(5, 0))
+        );
+        assert!(matches!(
+            prices.latest_rate("EUR", "USD"),
+            Err(PricesError::NoSuchCommoditiesPair(_))
+        ));
+
+        assert_eq!(
+            prices
+                .convert_latest(Decimal::new(10, 0), "PLN", "EUR")
+                .unwrap(),
+            (date(2020, 1, 11), Decimal::new(2, 0))
+        );
+
+        let rates_table = &prices.rates[&CommoditiesPair::new("EUR", "PLN")];
+        assert_eq!(rates_table.first_date(), Some(date(2020, 1, 1)));
+        assert_eq!(rates_table.last_date(), Some(date(2020, 1, 11)));
+        assert_eq!(
+            rates_table.date_range(),
+            Some((date(2020, 1, 1), date(2020, 1, 11)))
+        );
+    }
+
+    #[test]
+    fn test_load_csv() {
+        let mut prices = Prices::new();
+        prices
+            .load_csv(
+                "date,src_commodity,dst_commodity,rate\n2020-01-01, EUR, PLN, 4.00\n\n2020-01-02,$,PLN,3.5\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::new(4, 0)
+        );
+        assert_eq!(
+            prices.get_rate("PLN", "EUR", date(2020, 1, 1)).unwrap(),
+            Decimal::new(25, 2)
+        );
+        assert_eq!(
+            prices.get_rate("$", "PLN", date(2020, 1, 2)).unwrap(),
+            Decimal::new(35, 1)
+        );
+    }
+
+    #[test]
+    fn test_load_csv_malformed_row() {
+        let mut prices = Prices::new();
+        let result = prices.load_csv("2020-01-01,EUR,PLN,4.00\n2020-01-02,EUR,PLN\n".as_bytes());
+        assert!(matches!(
+            result,
+            Err(PricesError::InvalidCsvRow { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let input1 = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-02 00:00:00 EUR 4.10 PLN
+"#;
+        let input2 = r#"
+P 2020-01-01 00:00:00 EUR 4.50 PLN
+P 2020-01-03 00:00:00 $ 3.50 PLN
+"#;
+
+        let mut prices = prices_from(input1);
+        prices.merge(prices_from(input2), MergePolicy::PreferSelf);
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::new(4, 0)
+        );
+        assert_eq!(
+            prices.get_rate("$", "PLN", date(2020, 1, 3)).unwrap(),
+            Decimal::new(35, 1)
+        );
+
+        let mut prices = prices_from(input1);
+        prices.merge(prices_from(input2), MergePolicy::PreferOther);
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::new(45, 1)
+        );
+
+        let mut prices = prices_from(input1);
+        prices.merge(prices_from(input2), MergePolicy::PreferNewer);
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::new(4, 0)
+        );
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 2)).unwrap(),
+            Decimal::new(41, 1)
+        );
+    }
+
+    #[test]
+    fn test_zero_price() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 XYZ 0 PLN
+"#,
+        );
+        assert_eq!(
+            prices.get_rate("XYZ", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::ZERO
+        );
+        assert!(matches!(
+            prices.get_rate("PLN", "XYZ", date(2020, 1, 1)),
+            Err(PricesError::NoSuchCommoditiesPair(_))
+        ));
+    }
+
+    #[test]
+    fn test_same_day_prices_resolve_by_time() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 16:00:00 EUR 4.20 PLN
+P 2020-01-01 09:00:00 EUR 4.10 PLN
+"#,
+        );
+        assert_eq!(
+            prices.get_rate("EUR", "PLN", date(2020, 1, 1)).unwrap(),
+            Decimal::new(42, 1)
+        );
+    }
+
+    #[test]
+    fn test_convert_amount() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+"#,
+        );
+        let amount = Amount {
+            quantity: Decimal::new(25, 1),
+            commodity: Commodity {
+                name: "EUR".to_string(),
+                position: CommodityPosition::Left,
+            },
+        };
+        assert_eq!(
+            prices
+                .convert_amount(&amount, "PLN", CommodityPosition::Right, date(2020, 1, 1))
+                .unwrap(),
+            Amount {
+                quantity: Decimal::new(10, 0),
+                commodity: Commodity {
+                    name: "PLN".to_string(),
+                    position: CommodityPosition::Right,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_to_commodity_prices_round_trip() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-02 12:30:00 EUR 4.10 PLN
+P 2020-01-02 00:00:00 ADA 0.20 PLN
+"#,
+        );
+
+        let commodity_prices = prices.to_commodity_prices();
+        assert_eq!(commodity_prices.len(), 3);
+        assert_eq!(commodity_prices[0].commodity_name, "EUR");
+        assert_eq!(commodity_prices[1].commodity_name, "ADA");
+
+        let serialized: String = commodity_prices
+            .iter()
+            .map(|price| format!("{}\n", price))
+            .collect();
+        let reparsed = prices_from(&serialized);
+
+        assert_eq!(reparsed.rates.len(), prices.rates.len());
+        for (commodities_pair, rates_table) in &prices.rates {
+            assert_eq!(reparsed.rates[commodities_pair].table, rates_table.table);
+        }
+        assert_eq!(reparsed.to_commodity_prices(), commodity_prices);
+    }
+
+    #[test]
+    fn test_get_rate_fresh() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+"#,
+        );
+        assert_eq!(
+            prices
+                .get_rate_fresh("EUR", "PLN", date(2020, 1, 31), Duration::days(30))
+                .unwrap(),
+            Decimal::new(4, 0)
+        );
+        assert!(matches!(
+            prices.get_rate_fresh("EUR", "PLN", date(2020, 2, 1), Duration::days(30)),
+            Err(PricesError::StalePrice { last_date, .. }) if last_date == date(2020, 1, 1)
+        ));
+    }
+
+    #[test]
+    fn test_commodities_and_pairs() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-01 00:00:00 ADA 0.20 PLN
+"#,
+        );
+        assert_eq!(
+            prices.commodities().into_iter().collect::<Vec<_>>(),
+            vec!["ADA", "EUR", "PLN"]
+        );
+        assert_eq!(
+            prices.pairs(),
+            vec![
+                CommoditiesPair::new("ADA", "PLN"),
+                CommoditiesPair::new("EUR", "PLN"),
+                CommoditiesPair::new("PLN", "ADA"),
+                CommoditiesPair::new("PLN", "EUR"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prices_from_transaction_with_fee() {
+        let ledger: Ledger = r#"
+2020-02-01 Buy ADA
+  assets:cc:ada          2000 ADA
+  expenses:fees            $1
+  assets:bank:checking   $-41
+"#
+        .parse()
+        .unwrap();
+
+        // the fee is ignored, so the price includes it
+        let mut prices = Prices::new();
+        prices.insert_from_with(&ledger, &|account: &str| {
+            account.starts_with("expenses:fees")
+        });
+        assert_eq!(
+            prices.get_rate("ADA", "$", date(2020, 2, 1)).unwrap(),
+            Decimal::new(205, 4)
+        );
+
+        // the fee nets out with the checking account
+        let mut prices = Prices::new();
+        prices.insert_from(&ledger);
+        assert_eq!(
+            prices.get_rate("ADA", "$", date(2020, 2, 1)).unwrap(),
+            Decimal::new(2, 2)
+        );
+    }
+
+    #[test]
+    fn test_prices_from_explicit_posting_prices() {
+        let prices = prices_from(
+            r#"
+2020-02-01 Buy ADA
+  assets:cc:ada          2000 ADA @ $0.02
+  assets:bank:checking   $-40.01
+
+2020-02-02 Buy ADA
+  assets:cc:ada          1000 ADA @@ $25
+  assets:bank:checking   $-25
+
+2020-02-03 Sell ADA
+  assets:cc:ada          -1000 ADA @@ $30
+  assets:bank:checking   $30
+"#,
+        );
+        assert_eq!(
+            prices.get_rate("ADA", "$", date(2020, 2, 1)).unwrap(),
+            Decimal::new(2, 2)
+        );
+        assert_eq!(
+            prices.get_rate("ADA", "$", date(2020, 2, 2)).unwrap(),
+            Decimal::new(25, 3)
+        );
+        assert_eq!(
+            prices.get_rate("ADA", "$", date(2020, 2, 3)).unwrap(),
+            Decimal::new(3, 2)
+        );
+    }
+
+    #[test]
+    fn display_prices_error() {
+        assert_eq!(
+            PricesError::NoSuchCommoditiesPair(CommoditiesPair::new("ADA", "PLN")).to_string(),
+            "No price for ADA->PLN"
+        );
+        assert_eq!(
+            PricesError::DateTooEarly(date(2020, 1, 1)).to_string(),
+            "No rate available on or before 2020-01-01"
+        );
+    }
+
+    #[test]
+    fn test_get_rate_no_intermediate_commodity() {
+        let prices = prices_from(
+            r#"
+P 2020-01-01 00:00:00 ADA $0.05
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+"#,
+        );
+        assert!(matches!(
+            prices.get_rate("ADA", "PLN", date(2020, 2, 1)),
+            Err(PricesError::NoSuchCommoditiesPair(_))
+        ));
+    }
+}
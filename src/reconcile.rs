@@ -0,0 +1,147 @@
+use crate::{Amount, Ledger, TransactionStatus};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Not yet cleared posting of the reconciled account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingPosting {
+    pub date: NaiveDate,
+    pub description: String,
+    pub amount: Amount,
+}
+
+/// Result of comparing the cleared balance of an account with a statement figure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    pub account: String,
+    pub as_of: NaiveDate,
+    pub expected: Amount,
+
+    /// Sum of cleared postings in the commodity of `expected`.
+    pub cleared: Amount,
+
+    /// `expected - cleared`.
+    pub difference: Decimal,
+
+    /// Pending postings dated on or before `as_of`, sorted by date.
+    pub pending: Vec<PendingPosting>,
+}
+
+impl Reconciliation {
+    pub fn is_reconciled(&self) -> bool {
+        self.difference.is_zero()
+    }
+}
+
+/// Compares the balance of cleared postings to `account` dated on or before `as_of`
+/// with the `expected` amount (e.g. from a bank statement).
+///
+/// Only postings in the commodity of `expected` are taken into account.
+/// Posting status overrides the status of its transaction.
+pub fn reconcile(
+    ledger: &Ledger,
+    account: &str,
+    as_of: NaiveDate,
+    expected: &Amount,
+) -> Reconciliation {
+    let mut cleared = Amount {
+        quantity: Decimal::ZERO,
+        commodity: expected.commodity.clone(),
+    };
+    let mut pending = Vec::new();
+
+    for transaction in &ledger.transactions {
+        for posting in &transaction.postings {
+            if posting.account != account
+                || posting.date > as_of
+                || posting.amount.commodity.name != expected.commodity.name
+            {
+                continue;
+            }
+
+            match posting.status.or(transaction.status) {
+                Some(TransactionStatus::Cleared) => cleared.quantity += posting.amount.quantity,
+                Some(TransactionStatus::Pending) => pending.push(PendingPosting {
+                    date: posting.date,
+                    description: transaction.description.clone(),
+                    amount: posting.amount.clone(),
+                }),
+                None => (),
+            }
+        }
+    }
+    pending.sort_by_key(|posting| posting.date);
+
+    Reconciliation {
+        account: account.to_string(),
+        as_of,
+        expected: expected.clone(),
+        difference: expected.quantity - cleared.quantity,
+        cleared,
+        pending,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::date;
+
+    #[test]
+    fn test_reconcile() {
+        let ledger: Ledger = r#"
+2020-01-01 * Opening balance
+  Assets:Bank  $1000
+  Equity:Opening
+
+2020-01-10 * Groceries
+  Expenses:Food  $50
+  Assets:Bank
+
+2020-01-20 ! Rent
+  Expenses:Rent  $500
+  Assets:Bank
+
+2020-01-25 Mixed
+  Expenses:Fees  $5
+  ! Assets:Bank  $-5
+
+2020-01-28 Unmarked
+  Expenses:Food  $20
+  Assets:Bank
+
+2020-02-05 * Later
+  Expenses:Food  $10
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let expected: Amount = ledger.transactions[0].postings[0].amount.clone();
+        let statement = Amount {
+            quantity: Decimal::new(950, 0),
+            ..expected.clone()
+        };
+
+        let reconciliation = reconcile(&ledger, "Assets:Bank", date(2020, 1, 31), &statement);
+        assert!(reconciliation.is_reconciled());
+        assert_eq!(reconciliation.cleared, statement);
+        let pending: Vec<_> = reconciliation
+            .pending
+            .iter()
+            .map(|posting| (posting.description.as_str(), posting.amount.to_string()))
+            .collect();
+        assert_eq!(
+            pending,
+            vec![("Rent", "$-500".to_string()), ("Mixed", "$-5".to_string())]
+        );
+
+        let statement = Amount {
+            quantity: Decimal::new(450, 0),
+            ..expected
+        };
+        let reconciliation = reconcile(&ledger, "Assets:Bank", date(2020, 1, 31), &statement);
+        assert!(!reconciliation.is_reconciled());
+        assert_eq!(reconciliation.difference, Decimal::new(-500, 0));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::account_balance::AccountBalance;
+use crate::{Amount, Ledger};
+use chrono::NaiveDate;
+
+/// Single posting of a register report.
+#[derive(Debug, Clone)]
+pub struct RegisterEntry {
+    pub date: NaiveDate,
+    pub description: String,
+    pub account: String,
+    pub amount: Amount,
+
+    /// Balance of the account after this posting.
+    pub running: AccountBalance,
+}
+
+/// Lists postings to `account` (or its sub-accounts) ordered by posting date,
+/// each with the running balance of the account.
+///
+/// Postings with the same date keep the order of the ledger.
+pub fn register(ledger: &Ledger, account: &str) -> Vec<RegisterEntry> {
+    let sub_account_prefix = format!("{}:", account);
+    let mut postings: Vec<_> = ledger
+        .postings()
+        .filter(|(_, _, posting)| {
+            posting.account == account || posting.account.starts_with(&sub_account_prefix)
+        })
+        .collect();
+    postings.sort_by_key(|(date, _, _)| *date);
+
+    let mut running = AccountBalance::new();
+    postings
+        .into_iter()
+        .map(|(date, transaction, posting)| {
+            running += &posting.amount;
+            RegisterEntry {
+                date,
+                description: transaction.description.clone(),
+                account: posting.account.clone(),
+                amount: posting.amount.clone(),
+                running: running.clone(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        let ledger: Ledger = r#"
+2020-01-20 Food
+  Expenses:Food  $5
+  Assets:Bank:Checking
+
+2020-01-15 Salary
+  Assets:Bank:Checking  $100
+  Income:Salary
+
+2020-01-25 Exchange
+  Assets:Bank:Savings  10 EUR
+  Assets:Bank:Checking  $-11
+  Assets:Banknotes  $1
+"#
+        .parse()
+        .unwrap();
+
+        let entries = register(&ledger, "Assets:Bank");
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.date.to_string(),
+                    entry.description.as_str(),
+                    entry.amount.to_string(),
+                    entry.running.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "2020-01-15".to_string(),
+                    "Salary",
+                    "$100".to_string(),
+                    "$100".to_string()
+                ),
+                (
+                    "2020-01-20".to_string(),
+                    "Food",
+                    "$-5".to_string(),
+                    "$95".to_string()
+                ),
+                (
+                    "2020-01-25".to_string(),
+                    "Exchange",
+                    "10 EUR".to_string(),
+                    "$95, 10 EUR".to_string()
+                ),
+                (
+                    "2020-01-25".to_string(),
+                    "Exchange",
+                    "$-11".to_string(),
+                    "$84, 10 EUR".to_string()
+                ),
+            ]
+        );
+    }
+}
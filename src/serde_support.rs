@@ -0,0 +1,76 @@
+//! Serde representations of `ledger_parser` types used by this crate.
+
+use crate::account_balance::AccountBalance;
+use crate::{Amount, Commodity, CommodityPosition};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum SerdeCommodityPosition {
+    Left,
+    Right,
+}
+
+/// Amount of a commodity. The quantity is serialized as a string
+/// to avoid precision loss.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SerdeAmount {
+    #[serde(with = "rust_decimal::serde::str")]
+    quantity: Decimal,
+    position: SerdeCommodityPosition,
+}
+
+/// `AccountBalance` serialized as a map from commodity names to amounts.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct SerdeAccountBalance(HashMap<String, SerdeAmount>);
+
+impl From<AccountBalance> for SerdeAccountBalance {
+    fn from(account_balance: AccountBalance) -> Self {
+        SerdeAccountBalance(
+            account_balance
+                .amounts
+                .into_iter()
+                .map(|(commodity_name, amount)| {
+                    let position = match amount.commodity.position {
+                        CommodityPosition::Left => SerdeCommodityPosition::Left,
+                        CommodityPosition::Right => SerdeCommodityPosition::Right,
+                    };
+                    (
+                        commodity_name,
+                        SerdeAmount {
+                            quantity: amount.quantity,
+                            position,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<SerdeAccountBalance> for AccountBalance {
+    fn from(account_balance: SerdeAccountBalance) -> Self {
+        AccountBalance {
+            amounts: account_balance
+                .0
+                .into_iter()
+                .map(|(commodity_name, amount)| {
+                    let position = match amount.position {
+                        SerdeCommodityPosition::Left => CommodityPosition::Left,
+                        SerdeCommodityPosition::Right => CommodityPosition::Right,
+                    };
+                    let amount = Amount {
+                        quantity: amount.quantity,
+                        commodity: Commodity {
+                            name: commodity_name.clone(),
+                            position,
+                        },
+                    };
+                    (commodity_name, amount)
+                })
+                .collect(),
+        }
+    }
+}
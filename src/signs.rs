@@ -0,0 +1,130 @@
+use crate::account_balance::AccountBalance;
+use crate::Ledger;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Posting after which an account balance got an unexpected sign.
+#[derive(Debug, Clone)]
+pub struct SuspiciousSign {
+    pub date: NaiveDate,
+    pub description: String,
+    pub account: String,
+
+    /// Balance of the account after the posting.
+    pub balance: AccountBalance,
+}
+
+/// Finds postings that make an asset account negative or a liability account positive,
+/// which often means signs were flipped on import. The ledger is not modified.
+///
+/// Postings are processed in date order. Only the posting that changes the sign is reported,
+/// not the following ones while the balance stays wrong.
+pub fn check_signs<FA, FL>(ledger: &Ledger, is_asset: &FA, is_liability: &FL) -> Vec<SuspiciousSign>
+where
+    FA: Fn(&str) -> bool,
+    FL: Fn(&str) -> bool,
+{
+    let mut postings: Vec<_> = ledger.postings().collect();
+    postings.sort_by_key(|(date, _, _)| *date);
+
+    let mut balances: HashMap<&str, AccountBalance> = HashMap::new();
+    let mut result = Vec::new();
+
+    for (date, transaction, posting) in postings {
+        let has_wrong_sign: &dyn Fn(Decimal) -> bool = if is_asset(&posting.account) {
+            &|quantity| quantity < Decimal::ZERO
+        } else if is_liability(&posting.account) {
+            &|quantity| quantity > Decimal::ZERO
+        } else {
+            continue;
+        };
+
+        let balance = balances.entry(posting.account.as_str()).or_default();
+        let commodity_name = &posting.amount.commodity.name;
+        let quantity_of = |balance: &AccountBalance| {
+            balance
+                .amounts
+                .get(commodity_name)
+                .map_or(Decimal::ZERO, |amount| amount.quantity)
+        };
+
+        let was_wrong = has_wrong_sign(quantity_of(balance));
+        *balance += &posting.amount;
+        if !was_wrong && has_wrong_sign(quantity_of(balance)) {
+            result.push(SuspiciousSign {
+                date,
+                description: transaction.description.clone(),
+                account: posting.account.clone(),
+                balance: balance.clone(),
+            });
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_signs() {
+        let ledger: Ledger = r#"
+2020-01-01 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-05 Card (flipped)
+  Expenses:Food  $-30
+  Liabilities:Card  $30
+
+2020-01-12 Rent
+  Expenses:Rent  $150
+  Assets:Bank
+
+2020-01-15 Food
+  Expenses:Food  $10
+  Assets:Bank
+
+2020-01-20 Salary
+  Assets:Bank  $100
+  Income:Salary
+
+2020-01-25 Food
+  Expenses:Food  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let suspicious: Vec<_> = check_signs(
+            &ledger,
+            &|account: &str| account.starts_with("Assets"),
+            &|account: &str| account.starts_with("Liabilities"),
+        )
+        .into_iter()
+        .map(|s| (s.description, s.account, s.balance.to_string()))
+        .collect();
+        assert_eq!(
+            suspicious,
+            vec![
+                (
+                    "Card (flipped)".to_string(),
+                    "Liabilities:Card".to_string(),
+                    "$30".to_string()
+                ),
+                (
+                    "Rent".to_string(),
+                    "Assets:Bank".to_string(),
+                    "$-50".to_string()
+                ),
+                (
+                    "Food".to_string(),
+                    "Assets:Bank".to_string(),
+                    "$-10".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,16 +1,499 @@
+use crate::account_balance::AccountBalance;
+use crate::aligned_serializer::{self, AlignMode, AmountFormat};
 use crate::*;
 use chrono::NaiveDate;
 use ledger_parser::{LedgerItem, Serializer, SerializerSettings, Tag, TagValue};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fmt, io};
+use std::{fmt, fs, io};
 
 ///
 /// Main document. Contains transactions and/or commodity prices.
 ///
+/// Comments and the `D`, `account` and `commodity` directives are kept (without comments
+/// on the directive lines), `include` lines are dropped. Sub-directives of `account` /
+/// `commodity` (indented lines below them) and other directives are not supported
+/// by `ledger-parser`, so journals containing them fail to parse.
+///
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Ledger {
     pub commodity_prices: Vec<ledger_parser::CommodityPrice>,
     pub transactions: Vec<Transaction>,
+    pub comments: Vec<LedgerComment>,
+
+    /// The `D` directive, setting the default commodity.
+    /// If there are several of them, the last one is kept.
+    pub default_commodity: Option<DefaultCommodity>,
+
+    /// Names declared with `account` directives, in their order.
+    pub declared_accounts: Vec<String>,
+
+    /// Names declared with `commodity` directives, in their order.
+    pub declared_commodities: Vec<String>,
+}
+
+///
+/// Overview of a ledger, like `hledger stats`. See `Ledger::stats`.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LedgerStats {
+    /// Earliest and latest transaction dates.
+    pub first_date: Option<NaiveDate>,
+    pub last_date: Option<NaiveDate>,
+    pub transaction_count: usize,
+    pub posting_count: usize,
+    pub accounts: BTreeSet<String>,
+
+    /// Commodities of postings and prices.
+    pub commodities: BTreeSet<String>,
+}
+
+///
+/// The `D` directive. Besides the commodity, it sets its display format
+/// (e.g. digit grouping), so the amount is also kept as written.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DefaultCommodity {
+    pub amount: Amount,
+    /// Amount as written in the directive, e.g. `$1,000.00`.
+    pub text: String,
+}
+
+///
+/// Standalone comment block, not attached to any transaction.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LedgerComment {
+    /// Date of the transaction or commodity price following the comment,
+    /// `None` for comments at the end of the document.
+    pub date: Option<NaiveDate>,
+    pub comment: String,
+}
+
+impl Ledger {
+    /// Loads a ledger from a file, following `include` directives
+    /// (relative to the including file) and joining the results with `join_ledgers`.
+    ///
+    /// Every file is converted separately, so balance assertions
+    /// only see postings from the same file.
+    pub fn load_file(path: &Path) -> Result<Ledger, Error> {
+        let mut ledgers = Vec::new();
+        load_file_recursive(path, &mut Vec::new(), &mut ledgers)?;
+        Ok(join_ledgers::join_ledgers(ledgers))
+    }
+
+    /// Like `TryFrom<ledger_parser::Ledger>`, but doesn't stop at the first error.
+    /// Fails with errors of all problematic transactions, in order.
+    ///
+    /// Failing transactions are skipped, so they don't affect
+    /// balance assertions of the following ones.
+    pub fn validate_all(ledger: ledger_parser::Ledger) -> Result<Ledger, Vec<Error>> {
+        let (mut transactions, mut commodity_prices, comments) = split_items(ledger);
+
+        let mut errors = calculate_amounts::calculate_amounts_all(
+            &mut transactions,
+            &mut commodity_prices,
+            true,
+        );
+        dedup_commodity_prices(&mut commodity_prices);
+
+        let mut converted = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            match Transaction::try_from(transaction) {
+                Ok(transaction) => converted.push(transaction),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(Ledger {
+            transactions: converted,
+            commodity_prices,
+            comments,
+            default_commodity: None,
+            declared_accounts: Vec::new(),
+            declared_commodities: Vec::new(),
+        })
+    }
+
+    /// Serializes the ledger with the given indentation of postings
+    /// and line endings (e.g. `"\r\n"`).
+    pub fn write_with<W>(&self, writer: &mut W, indent: &str, eol: &str) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let settings = SerializerSettings::default()
+            .with_indent(indent)
+            .with_eol(eol);
+        self.write(writer, &settings)
+    }
+
+    /// Returns a canonical copy for formatting: transactions (stable) sorted by date,
+    /// prices by datetime, comments by date, and surrounding whitespace trimmed from
+    /// descriptions, account names and comments. Postings keep their order.
+    pub fn normalized(&self) -> Ledger {
+        let mut ledger =
+            join_ledgers::join_ledgers_sorted(vec![self.clone()], join_ledgers::SortKey::Date);
+
+        let trim = |text: &mut String| *text = text.trim().to_string();
+        for transaction in &mut ledger.transactions {
+            trim(&mut transaction.description);
+            if let Some(comment) = transaction.comment.as_mut() {
+                trim(comment);
+            }
+            for posting in &mut transaction.postings {
+                trim(&mut posting.account);
+                if let Some(comment) = posting.comment.as_mut() {
+                    trim(comment);
+                }
+            }
+        }
+        for comment in &mut ledger.comments {
+            trim(&mut comment.comment);
+        }
+
+        ledger
+    }
+
+    /// Returns a new ledger with transactions dated between `start` and `end` (inclusive).
+    /// `None` means unbounded. All commodity prices and standalone comments are kept.
+    /// Balance assertions are dropped, as they may not hold without the other transactions
+    /// (this applies to all the `filter_by_*` methods).
+    pub fn filter_by_date(&self, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Ledger {
+        let mut ledger = Ledger {
+            commodity_prices: self.commodity_prices.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .filter(|txn| start.is_none_or(|start| txn.date >= start))
+                .filter(|txn| end.is_none_or(|end| txn.date <= end))
+                .cloned()
+                .collect(),
+            comments: self.comments.clone(),
+            default_commodity: self.default_commodity.clone(),
+            declared_accounts: self.declared_accounts.clone(),
+            declared_commodities: self.declared_commodities.clone(),
+        };
+        ledger.drop_balance_assertions(|_| true);
+        ledger
+    }
+
+    /// Returns a new ledger with transactions that have at least one posting
+    /// to an account matching `predicate`. Other postings of kept transactions
+    /// are retained, so the transactions stay balanced.
+    pub fn filter_by_account<F>(&self, predicate: F) -> Ledger
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut ledger = Ledger {
+            commodity_prices: self.commodity_prices.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .filter(|txn| txn.postings.iter().any(|p| predicate(&p.account)))
+                .cloned()
+                .collect(),
+            comments: self.comments.clone(),
+            default_commodity: self.default_commodity.clone(),
+            declared_accounts: self.declared_accounts.clone(),
+            declared_commodities: self.declared_commodities.clone(),
+        };
+        ledger.drop_balance_assertions(|_| true);
+        ledger
+    }
+
+    /// Returns a new ledger with transactions whose description contains `substr`.
+    pub fn filter_by_description(&self, substr: &str) -> Ledger {
+        let mut ledger = Ledger {
+            commodity_prices: self.commodity_prices.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .filter(|txn| txn.description.contains(substr))
+                .cloned()
+                .collect(),
+            comments: self.comments.clone(),
+            default_commodity: self.default_commodity.clone(),
+            declared_accounts: self.declared_accounts.clone(),
+            declared_commodities: self.declared_commodities.clone(),
+        };
+        ledger.drop_balance_assertions(|_| true);
+        ledger
+    }
+
+    /// Iterates over all postings together with their (posting) date
+    /// and the transaction they belong to.
+    pub fn postings(&self) -> impl Iterator<Item = (NaiveDate, &Transaction, &Posting)> {
+        self.transactions.iter().flat_map(|txn| {
+            txn.postings
+                .iter()
+                .map(move |posting| (posting.date, txn, posting))
+        })
+    }
+
+    /// Returns the span of transaction dates and counts of transactions and postings,
+    /// with accounts and commodities in use.
+    pub fn stats(&self) -> LedgerStats {
+        let mut stats = LedgerStats {
+            first_date: self.transactions.iter().map(|txn| txn.date).min(),
+            last_date: self.transactions.iter().map(|txn| txn.date).max(),
+            transaction_count: self.transactions.len(),
+            ..LedgerStats::default()
+        };
+
+        for (_, _, posting) in self.postings() {
+            stats.posting_count += 1;
+            stats.accounts.insert(posting.account.clone());
+            stats
+                .commodities
+                .insert(posting.amount.commodity.name.clone());
+        }
+        for price in &self.commodity_prices {
+            stats.commodities.insert(price.commodity_name.clone());
+            stats
+                .commodities
+                .insert(price.amount.commodity.name.clone());
+        }
+
+        stats
+    }
+
+    /// Renames commodity `from` to `to` in postings and commodity prices.
+    pub fn rename_commodity(&mut self, from: &str, to: &str) {
+        self.apply_commodity_aliases(&HashMap::from([(from.to_string(), to.to_string())]));
+    }
+
+    /// Renames commodities in postings and commodity prices according to `map`
+    /// (alias -> commodity), so they are treated as one.
+    ///
+    /// Renamed amounts take the position of existing amounts of the target commodity.
+    /// Prices between a commodity and itself, resulting from aliasing, are removed.
+    /// Balance assertions in merged commodities are dropped.
+    pub fn apply_commodity_aliases(&mut self, map: &HashMap<String, String>) {
+        // the first amount of every commodity defines its position
+        let mut positions: HashMap<String, CommodityPosition> = HashMap::new();
+        for commodity in self
+            .transactions
+            .iter()
+            .flat_map(|txn| &txn.postings)
+            .map(|posting| &posting.amount.commodity)
+            .chain(
+                self.commodity_prices
+                    .iter()
+                    .map(|price| &price.amount.commodity),
+            )
+            .filter(|commodity| !map.contains_key(&commodity.name))
+        {
+            positions
+                .entry(commodity.name.clone())
+                .or_insert(commodity.position);
+        }
+
+        // commodities that get amounts of others
+        let merged: HashSet<&String> = map
+            .values()
+            .filter(|to| {
+                positions.contains_key(*to) || map.values().filter(|t| t == to).count() > 1
+            })
+            .collect();
+
+        let rename = |commodity: &mut Commodity| {
+            if let Some(to) = map.get(&commodity.name) {
+                commodity.name = to.clone();
+                if let Some(position) = positions.get(to) {
+                    commodity.position = *position;
+                }
+            }
+        };
+
+        for transaction in &mut self.transactions {
+            for posting in &mut transaction.postings {
+                rename(&mut posting.amount.commodity);
+                if let Some(ledger_parser::Balance::Amount(balance)) = &mut posting.balance {
+                    let target = map
+                        .get(&balance.commodity.name)
+                        .unwrap_or(&balance.commodity.name);
+                    if merged.contains(target) {
+                        posting.balance = None;
+                    } else {
+                        rename(&mut balance.commodity);
+                    }
+                }
+                for price in [&mut posting.lot_price, &mut posting.price]
+                    .into_iter()
+                    .flatten()
+                {
+                    match price {
+                        Price::Unit(amount) | Price::Total(amount) => rename(&mut amount.commodity),
+                    }
+                }
+            }
+        }
+        for price in &mut self.commodity_prices {
+            if let Some(to) = map.get(&price.commodity_name) {
+                price.commodity_name = to.clone();
+            }
+            rename(&mut price.amount.commodity);
+        }
+        self.commodity_prices
+            .retain(|price| price.commodity_name != price.amount.commodity.name);
+    }
+
+    /// Renames account `from` to `to` in all postings. Sub-accounts are not renamed.
+    /// If `to` already exists, the accounts are merged and their balance assertions dropped.
+    pub fn rename_account(&mut self, from: &str, to: &str) {
+        if self.postings().any(|(_, _, posting)| posting.account == to) {
+            self.drop_balance_assertions(|account| account == from || account == to);
+        }
+        for transaction in &mut self.transactions {
+            for posting in &mut transaction.postings {
+                if posting.account == from {
+                    posting.account = to.to_string();
+                }
+            }
+        }
+    }
+
+    /// Renames account `from_prefix` together with its sub-accounts,
+    /// e.g. `Expenses:Food:Lunch` becomes `Expenses:Groceries:Lunch`
+    /// for prefixes `Expenses:Food` and `Expenses:Groceries`.
+    /// Accounts that only share the beginning of a name (like `Expenses:Foodstuff`) are kept.
+    /// Balance assertions of merged accounts are dropped, like in `rename_account`.
+    pub fn rename_account_prefix(&mut self, from_prefix: &str, to_prefix: &str) {
+        let is_under = |account: &str, prefix: &str| {
+            account
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+        };
+        let targets: HashSet<String> = self
+            .postings()
+            .filter_map(|(_, _, posting)| posting.account.strip_prefix(from_prefix))
+            .filter(|rest| rest.is_empty() || rest.starts_with(':'))
+            .map(|rest| format!("{}{}", to_prefix, rest))
+            .collect();
+        if self.postings().any(|(_, _, posting)| {
+            targets.contains(&posting.account) && !is_under(&posting.account, from_prefix)
+        }) {
+            self.drop_balance_assertions(|account| {
+                is_under(account, from_prefix) || is_under(account, to_prefix)
+            });
+        }
+        for transaction in &mut self.transactions {
+            for posting in &mut transaction.postings {
+                if let Some(rest) = posting.account.strip_prefix(from_prefix) {
+                    if rest.is_empty() || rest.starts_with(':') {
+                        posting.account = format!("{}{}", to_prefix, rest);
+                    }
+                }
+            }
+        }
+    }
+
+    // Removes balance assertions of postings to matching accounts.
+    fn drop_balance_assertions<F>(&mut self, is_account: F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        for transaction in &mut self.transactions {
+            for posting in &mut transaction.postings {
+                if is_account(&posting.account) {
+                    posting.balance = None;
+                }
+            }
+        }
+    }
+
+    /// Checks that all posting accounts are declared with `account` directives
+    /// (`declared_accounts`), like ledger's `--strict`. Fails with the first undeclared account.
+    pub fn validate_declared(&self) -> Result<(), Error> {
+        match self
+            .postings()
+            .find(|(_, _, posting)| !self.declared_accounts.contains(&posting.account))
+        {
+            Some((_, _, posting)) => Err(Error::UndeclaredAccount(posting.account.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns postings having a tag named `name`, with any value.
+    pub fn postings_with_tag(&self, name: &str) -> Vec<(&Transaction, &Posting)> {
+        self.postings()
+            .filter(|(_, _, posting)| posting.tag(name).is_some())
+            .map(|(_, txn, posting)| (txn, posting))
+            .collect()
+    }
+
+    /// Like `postings_with_tag`, but the tag must have the given `value`.
+    pub fn postings_with_tag_value(
+        &self,
+        name: &str,
+        value: &TagValue,
+    ) -> Vec<(&Transaction, &Posting)> {
+        self.postings()
+            .filter(|(_, _, posting)| {
+                posting
+                    .tag(name)
+                    .is_some_and(|tag| tag.value.as_ref() == Some(value))
+            })
+            .map(|(_, txn, posting)| (txn, posting))
+            .collect()
+    }
+
+    /// Returns distinct values of tags named `name`, in order of first appearance.
+    pub fn tag_values(&self, name: &str) -> Vec<&TagValue> {
+        let mut values: Vec<&TagValue> = Vec::new();
+        for (_, _, posting) in self.postings() {
+            if let Some(value) = posting.tag(name).and_then(|tag| tag.value.as_ref()) {
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+}
+
+fn load_file_recursive(
+    path: &Path,
+    loading: &mut Vec<PathBuf>,
+    ledgers: &mut Vec<Ledger>,
+) -> Result<(), Error> {
+    let file_error = |e: io::Error| Error::FileError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    };
+
+    let canonical_path = path.canonicalize().map_err(file_error)?;
+    if loading.contains(&canonical_path) {
+        return Err(Error::IncludeCycle(path.to_path_buf()));
+    }
+
+    let content = fs::read_to_string(path).map_err(file_error)?;
+    let (content, directives) = take_directives(&content)?;
+    let mut ledger: ledger_parser::Ledger = content.parse()?;
+
+    loading.push(canonical_path);
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for item in &ledger.items {
+        if let LedgerItem::Include(file) = item {
+            load_file_recursive(&base_dir.join(file), loading, ledgers)?;
+        }
+    }
+    loading.pop();
+
+    ledger
+        .items
+        .retain(|item| !matches!(item, LedgerItem::Include(_)));
+    let mut ledger: Ledger = ledger.try_into()?;
+    directives.apply_to(&mut ledger);
+    ledgers.push(ledger);
+
+    Ok(())
 }
 
 impl fmt::Display for Ledger {
@@ -29,26 +512,189 @@ impl Serializer for Ledger {
     where
         W: io::Write,
     {
-        let mut first = true;
+        self.write_aligned(writer, settings, None, &AmountFormat::default())
+    }
+}
 
-        for commodity_price in &self.commodity_prices {
-            first = false;
-            commodity_price.write(writer, settings)?;
-            writeln!(writer)?;
-        }
+impl Ledger {
+    /// Like `Serializer::write`, but amounts of postings are aligned
+    /// according to `align` (if given).
+    pub(crate) fn write_aligned<W>(
+        &self,
+        writer: &mut W,
+        settings: &SerializerSettings,
+        align: Option<AlignMode>,
+        amount_format: &AmountFormat,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut previous = None;
+
+        for item in self.ordered_items() {
+            if item.needs_empty_line_after(previous) {
+                write!(writer, "{}", settings.eol)?;
+            }
 
-        for transaction in &self.transactions {
-            if !first {
-                writeln!(writer)?;
+            match item {
+                OrderedItem::DefaultCommodity(default_commodity) => {
+                    write!(writer, "D {}", default_commodity.text)?;
+                }
+                OrderedItem::DeclaredCommodity(name) => {
+                    write!(writer, "commodity {}", name)?;
+                }
+                OrderedItem::DeclaredAccount(name) => {
+                    write!(writer, "account {}", name)?;
+                }
+                OrderedItem::Comment(comment) => {
+                    for (i, line) in comment.comment.split('\n').enumerate() {
+                        if i > 0 {
+                            write!(writer, "{}", settings.eol)?;
+                        }
+                        write!(writer, "; {}", line)?;
+                    }
+                }
+                OrderedItem::CommodityPrice(commodity_price) => {
+                    commodity_price.write(writer, settings)?;
+                }
+                OrderedItem::Transaction(transaction) => {
+                    transaction.write_aligned(writer, settings, align, amount_format)?;
+                }
             }
 
-            first = false;
-            transaction.write(writer, settings)?;
-            writeln!(writer)?;
+            previous = Some(item);
+            write!(writer, "{}", settings.eol)?;
         }
 
         Ok(())
     }
+
+    /// Items in the order they are written.
+    ///
+    /// Directives go first: `D`, then `commodity` and `account` declarations.
+    /// Commodity prices and standalone comments are put back in place by date:
+    /// they go before the first transaction dated later than them.
+    fn ordered_items(&self) -> Vec<OrderedItem<'_>> {
+        let mut result: Vec<_> = self
+            .default_commodity
+            .iter()
+            .map(OrderedItem::DefaultCommodity)
+            .chain(
+                self.declared_commodities
+                    .iter()
+                    .map(|name| OrderedItem::DeclaredCommodity(name)),
+            )
+            .chain(
+                self.declared_accounts
+                    .iter()
+                    .map(|name| OrderedItem::DeclaredAccount(name)),
+            )
+            .collect();
+
+        let mut comments = self.comments.iter().peekable();
+        let mut commodity_prices = self.commodity_prices.iter().peekable();
+        let mut transactions = self.transactions.iter().peekable();
+
+        loop {
+            // comments go first, then prices, then transactions on the same date
+            let comment_key = comments
+                .peek()
+                .map(|comment| (comment.date.is_none(), comment.date, 0));
+            let price_key = commodity_prices
+                .peek()
+                .map(|price| (false, Some(price.datetime.date()), 1));
+            let transaction_key = transactions
+                .peek()
+                .map(|transaction| (false, Some(transaction.date), 2));
+
+            let next = [comment_key, price_key, transaction_key]
+                .into_iter()
+                .flatten()
+                .min();
+
+            result.push(match next {
+                Some((_, _, 0)) => OrderedItem::Comment(comments.next().unwrap()),
+                Some((_, _, 1)) => OrderedItem::CommodityPrice(commodity_prices.next().unwrap()),
+                Some(_) => OrderedItem::Transaction(transactions.next().unwrap()),
+                None => break,
+            });
+        }
+
+        result
+    }
+}
+
+#[derive(Clone, Copy)]
+enum OrderedItem<'a> {
+    DefaultCommodity(&'a DefaultCommodity),
+    DeclaredCommodity(&'a str),
+    DeclaredAccount(&'a str),
+    Comment(&'a LedgerComment),
+    CommodityPrice(&'a CommodityPrice),
+    Transaction(&'a Transaction),
+}
+
+impl OrderedItem<'_> {
+    /// Items are separated by empty lines, except consecutive commodity prices
+    /// and consecutive directives.
+    fn needs_empty_line_after(&self, previous: Option<OrderedItem>) -> bool {
+        match previous {
+            None => false,
+            Some(OrderedItem::CommodityPrice(_)) => !matches!(self, OrderedItem::CommodityPrice(_)),
+            Some(previous) if previous.is_directive() => !self.is_directive(),
+            Some(_) => true,
+        }
+    }
+
+    fn is_directive(&self) -> bool {
+        matches!(
+            self,
+            OrderedItem::DefaultCommodity(_)
+                | OrderedItem::DeclaredCommodity(_)
+                | OrderedItem::DeclaredAccount(_)
+        )
+    }
+}
+
+impl From<Ledger> for ledger_parser::Ledger {
+    /// Transactions are written with all amounts and posting prices. Prices inferred
+    /// from postings are kept as commodity prices; converting the result back infers
+    /// them again, but repeated prices are dropped, so the conversion round-trips.
+    ///
+    /// `ledger_parser` has no items for the `D`, `account` and `commodity` directives,
+    /// so they are dropped.
+    fn from(ledger: Ledger) -> Self {
+        let mut items = Vec::new();
+        let mut previous = None;
+
+        for item in ledger.ordered_items() {
+            if item.needs_empty_line_after(previous) {
+                items.push(LedgerItem::EmptyLine);
+            }
+
+            match item {
+                OrderedItem::DefaultCommodity(_)
+                | OrderedItem::DeclaredCommodity(_)
+                | OrderedItem::DeclaredAccount(_) => continue,
+                OrderedItem::Comment(comment) => items.extend(
+                    comment
+                        .comment
+                        .split('\n')
+                        .map(|line| LedgerItem::LineComment(line.to_string())),
+                ),
+                OrderedItem::CommodityPrice(commodity_price) => {
+                    items.push(LedgerItem::CommodityPrice(commodity_price.clone()))
+                }
+                OrderedItem::Transaction(transaction) => {
+                    items.push(LedgerItem::Transaction(transaction.clone().into()))
+                }
+            }
+
+            previous = Some(item);
+        }
+
+        ledger_parser::Ledger { items }
+    }
 }
 
 #[non_exhaustive]
@@ -56,11 +702,23 @@ impl Serializer for Ledger {
 pub enum Error {
     ParseError(ledger_parser::ParseError),
     IncompleteTransaction(Box<ledger_parser::Posting>),
-    UnbalancedTransaction(Box<ledger_parser::Transaction>),
+    /// The transaction together with its residual amounts.
+    UnbalancedTransaction(Box<ledger_parser::Transaction>, AccountBalance),
     BalanceAssertionFailed(Box<ledger_parser::Transaction>),
     ZeroBalanceAssertionFailed(Box<ledger_parser::Transaction>),
     UnbalancedVirtualWithNoAmount(Box<ledger_parser::Transaction>),
     ZeroBalanceMultipleCurrencies(Box<ledger_parser::Transaction>),
+    FileError {
+        path: PathBuf,
+        message: String,
+    },
+    IncludeCycle(PathBuf),
+    InvalidCsvRow {
+        line: usize,
+        message: String,
+    },
+    UndeclaredAccount(String),
+    InconsistentPostingDates(Box<Transaction>),
 }
 
 impl std::error::Error for Error {}
@@ -74,8 +732,8 @@ impl fmt::Display for Error {
             Error::IncompleteTransaction(p) => {
                 write!(f, "Incomplete transaction:\n{}", p)
             }
-            Error::UnbalancedTransaction(t) => {
-                write!(f, "Unbalanced transaction:\n{}", t)
+            Error::UnbalancedTransaction(t, residual) => {
+                write!(f, "Unbalanced transaction (residual: {}):\n{}", residual, t)
             }
             Error::BalanceAssertionFailed(t) => {
                 write!(f, "Balance assertion failed:\n{}", t)
@@ -89,6 +747,21 @@ impl fmt::Display for Error {
             Error::ZeroBalanceMultipleCurrencies(t) => {
                 write!(f, "Zero balance with multiple currencies:\n{}", t)
             }
+            Error::FileError { path, message } => {
+                write!(f, "Cannot read file {}: {}", path.display(), message)
+            }
+            Error::IncludeCycle(path) => {
+                write!(f, "Include cycle detected at {}", path.display())
+            }
+            Error::InvalidCsvRow { line, message } => {
+                write!(f, "Invalid CSV row at line {}: {}", line, message)
+            }
+            Error::UndeclaredAccount(account) => {
+                write!(f, "Undeclared account: {}", account)
+            }
+            Error::InconsistentPostingDates(t) => {
+                write!(f, "Posting dates can't be written unambiguously:\n{}", t)
+            }
         }
     }
 }
@@ -102,9 +775,95 @@ impl From<ledger_parser::ParseError> for Error {
 impl FromStr for Ledger {
     type Err = Error;
 
+    /// `D`, `account` and `commodity` directives are taken out before parsing,
+    /// as `ledger-parser` doesn't support them.
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        input.parse::<ledger_parser::Ledger>()?.try_into()
+        let (input, directives) = take_directives(input)?;
+        let mut ledger: Ledger = input.parse::<ledger_parser::Ledger>()?.try_into()?;
+        directives.apply_to(&mut ledger);
+        Ok(ledger)
+    }
+}
+
+/// Directives not supported by `ledger-parser`, see `take_directives`.
+#[derive(Debug, Default)]
+pub(crate) struct Directives {
+    default_commodity: Option<DefaultCommodity>,
+    declared_accounts: Vec<String>,
+    declared_commodities: Vec<String>,
+}
+
+impl Directives {
+    pub(crate) fn apply_to(self, ledger: &mut Ledger) {
+        ledger.default_commodity = self.default_commodity;
+        ledger.declared_accounts = self.declared_accounts;
+        ledger.declared_commodities = self.declared_commodities;
+    }
+}
+
+/// Prepares the input for `ledger-parser`, keeping line numbers of parse errors:
+///
+/// - replaces `D`, `account` and `commodity` directives with empty lines and returns them
+///   (of several `D` directives, the last one is returned),
+/// - starts comment blocks following an empty line with `#` instead of `;`, as otherwise
+///   `ledger-parser` reads them as comments of the last posting of a preceding transaction.
+pub(crate) fn take_directives(input: &str) -> Result<(String, Directives), Error> {
+    let mut directives = Directives::default();
+    let mut output = String::with_capacity(input.len());
+    let mut after_empty_line = false;
+
+    for line in input.split_inclusive('\n') {
+        let standalone_comment = after_empty_line && line.starts_with(';');
+        after_empty_line = standalone_comment || line.trim().is_empty();
+        if let Some(comment) = line.strip_prefix(';').filter(|_| standalone_comment) {
+            output.push('#');
+            output.push_str(comment);
+            continue;
+        }
+
+        let Some((directive, argument)) = line.split_once([' ', '\t']) else {
+            output.push_str(line);
+            continue;
+        };
+        // a comment or two spaces end the name
+        let name = argument
+            .split(';')
+            .next()
+            .and_then(|name| name.split("  ").next())
+            .and_then(|name| name.split('\t').next())
+            .unwrap_or_default()
+            .trim();
+
+        match directive {
+            "D" => {
+                // ledger-parser has no public amount parser, so parse it as a price
+                let text = argument.trim();
+                let price: ledger_parser::Ledger =
+                    format!("P 1970-01-01 00:00:00 D {}", text).parse()?;
+                directives.default_commodity =
+                    price.items.into_iter().find_map(|item| match item {
+                        LedgerItem::CommodityPrice(price) => Some(DefaultCommodity {
+                            amount: price.amount,
+                            text: text.to_string(),
+                        }),
+                        _ => None,
+                    });
+            }
+            "account" if !name.is_empty() => directives.declared_accounts.push(name.to_string()),
+            "commodity" if !name.is_empty() => {
+                directives.declared_commodities.push(name.to_string())
+            }
+            _ => {
+                output.push_str(line);
+                continue;
+            }
+        }
+        if line.ends_with('\n') {
+            output.push('\n');
+        }
     }
+
+    Ok((output, directives))
 }
 
 impl TryFrom<ledger_parser::Ledger> for Ledger {
@@ -115,50 +874,22 @@ impl TryFrom<ledger_parser::Ledger> for Ledger {
     ///
     /// "Balance assertions" are postings with both amount and balance provided. The calculated
     /// amount using the balance must match the given amount.
+    ///
+    /// A posting without amount that has to balance several commodities
+    /// is split into one posting per commodity (as `Posting` holds a single `Amount`).
+    ///
+    /// Repeated commodity prices (e.g. a `P` directive and the same price inferred
+    /// from a posting) are kept once.
     fn try_from(ledger: ledger_parser::Ledger) -> Result<Self, Self::Error> {
-        let mut transactions = Vec::<ledger_parser::Transaction>::new();
-        let mut commodity_prices = Vec::<ledger_parser::CommodityPrice>::new();
-
-        let mut current_comment: Option<String> = None;
-
-        for item in ledger.items {
-            match item {
-                LedgerItem::EmptyLine => {
-                    current_comment = None;
-                }
-                LedgerItem::LineComment(comment) => {
-                    if let Some(ref mut c) = current_comment {
-                        c.push('\n');
-                        c.push_str(&comment);
-                    } else {
-                        current_comment = Some(comment);
-                    }
-                }
-                LedgerItem::Transaction(mut transaction) => {
-                    if let Some(current_comment) = current_comment {
-                        let mut full_comment = current_comment;
-                        if let Some(ref transaction_comment) = transaction.comment {
-                            full_comment.push('\n');
-                            full_comment.push_str(transaction_comment);
-                        }
-                        transaction.comment = Some(full_comment);
-                    }
-                    current_comment = None;
-
-                    transactions.push(transaction);
-                }
-                LedgerItem::CommodityPrice(commodity_price) => {
-                    current_comment = None;
-                    commodity_prices.push(commodity_price);
-                }
-                _ => {}
-            }
-        }
+        let (mut transactions, mut commodity_prices, comments) = split_items(ledger);
 
-        calculate_amounts::calculate_amounts_from_balances(
+        calculate_amounts::calculate_amounts(
             &mut transactions,
             &mut commodity_prices,
+            Decimal::ZERO,
+            true,
         )?;
+        dedup_commodity_prices(&mut commodity_prices);
 
         Ok(Ledger {
             transactions: transactions
@@ -166,10 +897,104 @@ impl TryFrom<ledger_parser::Ledger> for Ledger {
                 .map(Transaction::try_from)
                 .collect::<Result<_, _>>()?,
             commodity_prices,
+            comments,
+            default_commodity: None,
+            declared_accounts: Vec::new(),
+            declared_commodities: Vec::new(),
         })
     }
 }
 
+/// Removes repeated commodity prices, like a `P` directive and the same price
+/// inferred from a posting, keeping the first of them.
+fn dedup_commodity_prices(commodity_prices: &mut Vec<CommodityPrice>) {
+    let mut seen = HashSet::new();
+    commodity_prices.retain(|price| {
+        seen.insert((
+            price.datetime,
+            price.commodity_name.clone(),
+            price.amount.quantity,
+            price.amount.commodity.name.clone(),
+        ))
+    });
+}
+
+/// Splits ledger items into transactions, prices and standalone comments.
+/// Line comments directly above a transaction become part of its comment.
+fn split_items(
+    ledger: ledger_parser::Ledger,
+) -> (
+    Vec<ledger_parser::Transaction>,
+    Vec<ledger_parser::CommodityPrice>,
+    Vec<LedgerComment>,
+) {
+    let mut transactions = Vec::<ledger_parser::Transaction>::new();
+    let mut commodity_prices = Vec::<ledger_parser::CommodityPrice>::new();
+    let mut comments = Vec::<LedgerComment>::new();
+
+    let mut current_comment: Option<String> = None;
+
+    // comment blocks not attached to a transaction, waiting for a date
+    let mut standalone_comments = Vec::<String>::new();
+
+    for item in ledger.items {
+        match item {
+            LedgerItem::EmptyLine => {
+                standalone_comments.extend(current_comment.take());
+            }
+            LedgerItem::LineComment(comment) => {
+                if let Some(ref mut c) = current_comment {
+                    c.push('\n');
+                    c.push_str(&comment);
+                } else {
+                    current_comment = Some(comment);
+                }
+            }
+            LedgerItem::Transaction(mut transaction) => {
+                comments.extend(standalone_comments.drain(..).map(|comment| LedgerComment {
+                    date: Some(transaction.date),
+                    comment,
+                }));
+
+                if let Some(current_comment) = current_comment {
+                    let mut full_comment = current_comment;
+                    if let Some(ref transaction_comment) = transaction.comment {
+                        full_comment.push('\n');
+                        full_comment.push_str(transaction_comment);
+                    }
+                    transaction.comment = Some(full_comment);
+                }
+                current_comment = None;
+
+                transactions.push(transaction);
+            }
+            LedgerItem::CommodityPrice(commodity_price) => {
+                standalone_comments.extend(current_comment.take());
+                comments.extend(standalone_comments.drain(..).map(|comment| LedgerComment {
+                    date: Some(commodity_price.datetime.date()),
+                    comment,
+                }));
+
+                commodity_prices.push(commodity_price);
+            }
+            // includes and any future directives are dropped
+            _ => {}
+        }
+    }
+
+    standalone_comments.extend(current_comment);
+    comments.extend(
+        standalone_comments
+            .into_iter()
+            .map(|comment| LedgerComment {
+                date: None,
+                comment,
+            }),
+    );
+
+    (transactions, commodity_prices, comments)
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Transaction {
     pub comment: Option<String>,
@@ -178,9 +1003,39 @@ pub struct Transaction {
     pub status: Option<TransactionStatus>,
     pub code: Option<String>,
     pub description: String,
+
+    /// Tags of the whole transaction (in comments below the first line).
+    pub tags: Vec<Tag>,
     pub postings: Vec<Posting>,
 }
 
+impl From<Transaction> for ledger_parser::Transaction {
+    /// Posting dates equal to the transaction dates are omitted.
+    fn from(transaction: Transaction) -> Self {
+        let postings = transaction
+            .postings
+            .iter()
+            .map(|posting| posting.elide_dates(&transaction).into())
+            .collect();
+
+        ledger_parser::Transaction {
+            status: transaction.status,
+            code: transaction.code,
+            description: transaction.description,
+            comment: transaction.comment,
+            date: transaction.date,
+            effective_date: Some(transaction.effective_date)
+                .filter(|effective_date| *effective_date != transaction.date),
+            posting_metadata: ledger_parser::PostingMetadata {
+                date: None,
+                effective_date: None,
+                tags: transaction.tags,
+            },
+            postings,
+        }
+    }
+}
+
 impl TryFrom<ledger_parser::Transaction> for Transaction {
     type Error = Error;
 
@@ -198,6 +1053,7 @@ impl TryFrom<ledger_parser::Transaction> for Transaction {
             status: transaction.status,
             code: transaction.code,
             description: transaction.description,
+            tags: transaction.posting_metadata.tags,
             postings: transaction
                 .postings
                 .into_iter()
@@ -214,6 +1070,188 @@ impl TryFrom<ledger_parser::Transaction> for Transaction {
 
 impl Serializer for Transaction {
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        self.write_aligned(writer, settings, None, &AmountFormat::default())
+    }
+}
+
+impl Transaction {
+    /// Checks that posting dates survive writing the transaction and parsing it back.
+    ///
+    /// Dates equal to the transaction ones are omitted and filled back in without loss
+    /// (`Posting::elide_dates`, `OptionalDatePosting::fill_dates`), but a comment line
+    /// that looks like a date annotation (e.g. `[2020-02-02]`) is read back as posting dates.
+    pub fn validate_posting_dates(&self) -> Result<(), Error> {
+        let inconsistent = || Error::InconsistentPostingDates(self.clone().into());
+
+        let parsed: ledger_parser::Ledger = self.to_string().parse()?;
+        let Some(LedgerItem::Transaction(parsed)) = parsed
+            .items
+            .into_iter()
+            .find(|item| matches!(item, LedgerItem::Transaction(_)))
+        else {
+            return Err(inconsistent());
+        };
+        if parsed.postings.len() != self.postings.len() {
+            return Err(inconsistent());
+        }
+
+        for (parsed_posting, posting) in parsed.postings.into_iter().zip(&self.postings) {
+            let parsed_posting = OptionalDatePosting::try_from(parsed_posting)?
+                .fill_dates(parsed.date, parsed.effective_date);
+            if parsed_posting.date != posting.date
+                || parsed_posting.effective_date != posting.effective_date
+            {
+                return Err(inconsistent());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the sum of real postings by commodity, without zero amounts.
+    /// It's empty for a balanced transaction, unless it exchanges commodities.
+    pub fn imbalance(&self) -> AccountBalance {
+        let mut balance = AccountBalance::new();
+        for posting in &self.postings {
+            if posting.reality == Reality::Real {
+                balance += &posting.amount;
+            }
+        }
+        balance.amounts.retain(|_, amount| !amount.is_zero());
+        balance
+    }
+
+    /// Replaces the posting at `index` with one posting per split, to the split's account,
+    /// with the original amount distributed proportionally to the weights.
+    ///
+    /// Amounts are rounded to the decimal places of the original amount.
+    /// The rounding remainder goes to the last split, so the amounts sum exactly to the original.
+    /// Total prices (`@@`, `{{}}`) are split in proportion to the rounded amounts.
+    /// The balance assertion of the original posting is dropped.
+    ///
+    /// Panics if `index` is out of bounds or the weights sum to zero.
+    pub fn split_posting(&mut self, index: usize, splits: &[(String, Decimal)]) {
+        let total_weight: Decimal = splits.iter().map(|(_, weight)| weight).sum();
+        assert!(!total_weight.is_zero(), "weights sum to zero");
+
+        let original = self.postings.remove(index);
+        let mut remaining = original.amount.clone();
+        let mut remaining_prices = [original.lot_price.clone(), original.price.clone()];
+
+        let new_postings: Vec<Posting> = splits
+            .iter()
+            .enumerate()
+            .map(|(i, (account, weight))| {
+                let is_last = i + 1 == splits.len();
+                let split = |amount: &mut Amount, remaining: &mut Amount, ratio: Decimal| {
+                    if is_last {
+                        amount.quantity = remaining.quantity;
+                    } else {
+                        amount.quantity = (amount.quantity * ratio).round_dp_with_strategy(
+                            amount.quantity.scale(),
+                            RoundingStrategy::MidpointAwayFromZero,
+                        );
+                    }
+                    remaining.quantity -= amount.quantity;
+                };
+
+                let mut posting = original.clone();
+                posting.account = account.clone();
+                posting.balance = None;
+                split(&mut posting.amount, &mut remaining, weight / total_weight);
+
+                // total prices follow the rounded amount
+                let ratio = if original.amount.quantity.is_zero() {
+                    weight / total_weight
+                } else {
+                    posting.amount.quantity / original.amount.quantity
+                };
+                for (price, remaining_price) in [&mut posting.lot_price, &mut posting.price]
+                    .into_iter()
+                    .zip(&mut remaining_prices)
+                {
+                    if let (Some(Price::Total(total)), Some(Price::Total(remaining_total))) =
+                        (price, remaining_price)
+                    {
+                        split(total, remaining_total, ratio);
+                    }
+                }
+                posting
+            })
+            .collect();
+
+        self.postings.splice(index..index, new_postings);
+    }
+
+    /// Merges postings with the same account, commodity and reality into the first of them.
+    /// Amounts are summed and comments concatenated (as separate lines).
+    /// Dates and status of the first posting are kept; tags are combined.
+    /// Postings with different unit prices or with a total price (`@@`, `{{}}`) are left separate.
+    pub fn consolidate_postings(&mut self) {
+        self.consolidate_postings_by(|_, _| true);
+    }
+
+    /// Like `consolidate_postings`, but postings with different dates,
+    /// status or tags are left separate, so no metadata is lost.
+    pub fn consolidate_postings_with_same_metadata(&mut self) {
+        self.consolidate_postings_by(|a, b| {
+            a.date == b.date
+                && a.effective_date == b.effective_date
+                && a.status == b.status
+                && a.tags == b.tags
+        });
+    }
+
+    fn consolidate_postings_by<F>(&mut self, can_merge: F)
+    where
+        F: Fn(&Posting, &Posting) -> bool,
+    {
+        let mut postings: Vec<Posting> = Vec::with_capacity(self.postings.len());
+        for posting in self.postings.drain(..) {
+            let target = postings.iter_mut().find(|p| {
+                p.account == posting.account
+                    && p.amount.commodity.name == posting.amount.commodity.name
+                    && p.reality == posting.reality
+                    && p.lot_price == posting.lot_price
+                    && p.price == posting.price
+                    && ![&p.lot_price, &p.price]
+                        .into_iter()
+                        .any(|price| matches!(price, Some(Price::Total(_))))
+                    && can_merge(p, &posting)
+            });
+            match target {
+                Some(target) => {
+                    target.amount.quantity += posting.amount.quantity;
+                    // both assertions are about the balance after the transaction
+                    target.balance = target.balance.take().or(posting.balance);
+                    target.comment = match (target.comment.take(), posting.comment) {
+                        (Some(a), Some(b)) => Some(format!("{}\n{}", a, b)),
+                        (a, b) => a.or(b),
+                    };
+                    for tag in posting.tags {
+                        if !target.tags.contains(&tag) {
+                            target.tags.push(tag);
+                        }
+                    }
+                }
+                None => postings.push(posting),
+            }
+        }
+        self.postings = postings;
+    }
+
+    /// Like `Serializer::write`, but amounts of postings are aligned
+    /// according to `align` (if given).
+    pub(crate) fn write_aligned<W>(
+        &self,
+        writer: &mut W,
+        settings: &SerializerSettings,
+        align: Option<AlignMode>,
+        amount_format: &AmountFormat,
+    ) -> Result<(), io::Error>
     where
         W: io::Write,
     {
@@ -242,9 +1280,27 @@ impl Serializer for Transaction {
             }
         }
 
+        write_tags(writer, &self.tags, settings, &mut false)?;
+
+        let align = align.map(|align| align.shifted(settings.indent.chars().count()));
+        let max_fraction_width = self
+            .postings
+            .iter()
+            .map(|posting| {
+                aligned_serializer::fraction_width(&amount_format.rounded(&posting.amount))
+            })
+            .max()
+            .unwrap_or(0);
+
         for posting in &self.postings {
             write!(writer, "{}{}", settings.eol, settings.indent)?;
-            posting.elide_dates(self).write(writer, settings)?;
+            posting.elide_dates(self).write_aligned(
+                writer,
+                settings,
+                align,
+                amount_format,
+                max_fraction_width,
+            )?;
         }
 
         Ok(())
@@ -269,6 +1325,9 @@ pub struct OptionalDatePosting {
     pub account: String,
     pub reality: Reality,
     pub amount: Amount,
+    pub lot_price: Option<Price>,
+    pub price: Option<Price>,
+    pub balance: Option<ledger_parser::Balance>,
     pub status: Option<TransactionStatus>,
     pub comment: Option<String>,
     pub tags: Vec<Tag>,
@@ -281,6 +1340,17 @@ pub struct Posting {
     pub account: String,
     pub reality: Reality,
     pub amount: Amount,
+
+    /// Lot price (`{}`) and price (`@` / `@@`) the amount was written with.
+    /// Amounts of the other postings are already filled in, so they are kept
+    /// only to write the posting back as it was.
+    pub lot_price: Option<Price>,
+    pub price: Option<Price>,
+
+    /// Balance assertion (`= AMOUNT`) the posting was written with, checked when
+    /// the ledger was parsed. Operations that could make it fail (like filtering
+    /// transactions or merging accounts) drop it.
+    pub balance: Option<ledger_parser::Balance>,
     pub status: Option<TransactionStatus>,
     pub comment: Option<String>,
     pub tags: Vec<Tag>,
@@ -298,6 +1368,9 @@ impl OptionalDatePosting {
             account: self.account,
             reality: self.reality,
             amount: self.amount,
+            lot_price: self.lot_price,
+            price: self.price,
+            balance: self.balance,
             status: self.status,
             comment: self.comment,
             tags: self.tags,
@@ -306,6 +1379,11 @@ impl OptionalDatePosting {
 }
 
 impl Posting {
+    /// Returns the first tag named `name`.
+    pub fn tag(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
     pub fn elide_dates(&self, txn: &Transaction) -> OptionalDatePosting {
         let date = if self.date != txn.date {
             Some(self.date)
@@ -325,6 +1403,9 @@ impl Posting {
             account: self.account.clone(),
             reality: self.reality,
             amount: self.amount.clone(),
+            lot_price: self.lot_price.clone(),
+            price: self.price.clone(),
+            balance: self.balance.clone(),
             status: self.status,
             comment: self.comment.clone(),
             tags: self.tags.clone(),
@@ -332,12 +1413,39 @@ impl Posting {
     }
 }
 
+impl From<OptionalDatePosting> for ledger_parser::Posting {
+    fn from(posting: OptionalDatePosting) -> Self {
+        ledger_parser::Posting {
+            account: posting.account,
+            reality: posting.reality,
+            amount: Some(ledger_parser::PostingAmount {
+                amount: posting.amount,
+                lot_price: posting.lot_price,
+                price: posting.price,
+            }),
+            balance: posting.balance,
+            status: posting.status,
+            comment: posting.comment,
+            metadata: ledger_parser::PostingMetadata {
+                date: posting.date,
+                effective_date: posting.effective_date,
+                tags: posting.tags,
+            },
+        }
+    }
+}
+
 impl TryFrom<ledger_parser::Posting> for OptionalDatePosting {
     type Error = Error;
 
-    /// Fails unless all `amount`s are `Some`. Ignores `balance`s.
+    /// Fails unless all `amount`s are `Some`. `balance`s are kept as balance assertions.
     fn try_from(posting: ledger_parser::Posting) -> Result<Self, Self::Error> {
-        if let Some(ledger_parser::PostingAmount { amount, .. }) = posting.amount {
+        if let Some(ledger_parser::PostingAmount {
+            amount,
+            lot_price,
+            price,
+        }) = posting.amount
+        {
             Ok(Self {
                 date: posting.metadata.date,
                 effective_date: posting.metadata.effective_date,
@@ -346,6 +1454,9 @@ impl TryFrom<ledger_parser::Posting> for OptionalDatePosting {
                 status: posting.status,
                 comment: posting.comment,
                 amount,
+                lot_price,
+                price,
+                balance: posting.balance,
                 tags: posting.metadata.tags,
             })
         } else {
@@ -359,19 +1470,57 @@ impl Serializer for OptionalDatePosting {
     where
         W: io::Write,
     {
+        self.write_aligned(writer, settings, None, &AmountFormat::default(), 0)
+    }
+}
+
+impl OptionalDatePosting {
+    /// Like `Serializer::write`, but the amount is aligned according to `align`
+    /// (columns counted from the start of the posting). Falls back to a two-space gap
+    /// if the account name is too long.
+    ///
+    /// `max_fraction_width` is the widest fractional part (with the decimal point)
+    /// among amounts being aligned, used by `AlignMode::DecimalPoint`.
+    ///
+    /// Metadata follows the amount in a fixed order: comment lines, `[date=effective_date]`,
+    /// then tags, so serializing a parsed posting again gives the same text.
+    pub(crate) fn write_aligned<W>(
+        &self,
+        writer: &mut W,
+        settings: &SerializerSettings,
+        align: Option<AlignMode>,
+        amount_format: &AmountFormat,
+        max_fraction_width: usize,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut account = String::new();
         if let Some(ref status) = self.status {
-            status.write(writer, settings)?;
-            write!(writer, " ")?;
+            account.push_str(&status.to_string_pretty(settings));
+            account.push(' ');
         }
 
         match self.reality {
-            Reality::Real => write!(writer, "{}", self.account)?,
-            Reality::BalancedVirtual => write!(writer, "[{}]", self.account)?,
-            Reality::UnbalancedVirtual => write!(writer, "({})", self.account)?,
+            Reality::Real => account.push_str(&self.account),
+            Reality::BalancedVirtual => account.push_str(&format!("[{}]", self.account)),
+            Reality::UnbalancedVirtual => account.push_str(&format!("({})", self.account)),
         }
 
-        write!(writer, "  ")?;
-        self.amount.write(writer, settings)?;
+        let (gap, amount) = aligned_serializer::format_amount(
+            account.chars().count(),
+            &self.amount,
+            align,
+            amount_format,
+            max_fraction_width,
+        );
+
+        write!(writer, "{}{:gap$}{}", account, "", amount)?;
+        write_prices(writer, &self.lot_price, &self.price, settings)?;
+        if let Some(ref balance) = self.balance {
+            write!(writer, " = ")?;
+            balance.write(writer, settings)?;
+        }
 
         let mut first = true;
 
@@ -404,38 +1553,71 @@ impl Serializer for OptionalDatePosting {
             write!(writer, "]")?;
         }
 
-        let (tags, tags_with_values): (Vec<_>, Vec<_>) =
-            self.tags.iter().partition(|t| t.value.is_none());
+        write_tags(writer, &self.tags, settings, &mut first)?;
 
-        if !tags.is_empty() {
-            if first {
-                first = false;
-                write!(writer, "  ")?;
-            } else {
-                write!(writer, "{}{}", settings.eol, settings.indent)?;
-            }
-            write!(writer, "; :")?;
-            for tag in tags {
-                write!(writer, "{}:", tag.name)?;
-            }
+        Ok(())
+    }
+}
+
+/// Writes lot price (`{}`) and price (`@` / `@@`) following an amount.
+fn write_prices<W>(
+    writer: &mut W,
+    lot_price: &Option<Price>,
+    price: &Option<Price>,
+    settings: &SerializerSettings,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    match lot_price {
+        Some(Price::Unit(amount)) => write!(writer, " {{{}}}", amount.to_string_pretty(settings))?,
+        Some(Price::Total(amount)) => {
+            write!(writer, " {{{{{}}}}}", amount.to_string_pretty(settings))?
         }
+        None => {}
+    }
+    match price {
+        Some(Price::Unit(amount)) => write!(writer, " @ {}", amount.to_string_pretty(settings))?,
+        Some(Price::Total(amount)) => write!(writer, " @@ {}", amount.to_string_pretty(settings))?,
+        None => {}
+    }
+    Ok(())
+}
 
-        for tag in tags_with_values {
-            if first {
-                first = false;
-                write!(writer, "  ")?;
-            } else {
-                write!(writer, "{}{}", settings.eol, settings.indent)?;
-            }
-            match &tag.value {
-                Some(TagValue::String(s)) => write!(writer, "; {}: {s}", tag.name)?,
-                Some(other_type) => write!(writer, "; {}:: {other_type}", tag.name)?,
-                None => unreachable!(),
-            }
+/// Writes tags in their original order, each on its own comment line,
+/// except that consecutive tags without values share a line (`; :a:b:`).
+/// If `first` is set, the first comment continues the current line.
+fn write_tags<W>(
+    writer: &mut W,
+    tags: &[Tag],
+    settings: &SerializerSettings,
+    first: &mut bool,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    let mut tags = tags.iter().peekable();
+    while let Some(tag) = tags.next() {
+        if *first {
+            *first = false;
+            write!(writer, "  ")?;
+        } else {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
         }
 
-        Ok(())
+        match &tag.value {
+            Some(TagValue::String(s)) => write!(writer, "; {}: {s}", tag.name)?,
+            Some(other_type) => write!(writer, "; {}:: {other_type}", tag.name)?,
+            None => {
+                write!(writer, "; :{}:", tag.name)?;
+                while let Some(tag) = tags.next_if(|tag| tag.value.is_none()) {
+                    write!(writer, "{}:", tag.name)?;
+                }
+            }
+        }
     }
+
+    Ok(())
 }
 
 impl fmt::Display for OptionalDatePosting {
@@ -486,6 +1668,812 @@ mod tests {
         assert_eq!(simplified_ledger.unwrap().commodity_prices.len(), 1);
     }
 
+    #[test]
+    fn test_blank_posting_across_commodities() {
+        let ledger: Ledger = r#"
+2020-02-01 Buy ADA
+  assets:cc:ada          2000 ADA @ $0.02
+  expenses:fees          1 EUR
+  assets:bank:checking  ; :card:
+"#
+        .parse()
+        .unwrap();
+
+        let postings: Vec<_> = ledger.transactions[0]
+            .postings
+            .iter()
+            .map(|posting| {
+                (
+                    posting.account.as_str(),
+                    posting.amount.to_string(),
+                    posting.tags.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            postings,
+            vec![
+                ("assets:cc:ada", "2000 ADA".to_string(), 0),
+                ("expenses:fees", "1 EUR".to_string(), 0),
+                ("assets:bank:checking", "$-40.00".to_string(), 1),
+                ("assets:bank:checking", "-1 EUR".to_string(), 1),
+            ]
+        );
+
+        let reparsed: Ledger = ledger
+            .to_string_pretty(&Default::default())
+            .parse()
+            .unwrap();
+        assert_eq!(reparsed.transactions, ledger.transactions);
+    }
+
+    #[test]
+    fn test_write_with() {
+        let ledger: Ledger = r#"
+; header
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+
+        let mut output = Vec::new();
+        ledger.write_with(&mut output, "    ", "\r\n").unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "; header\r\n\r\nP 2020-01-01 00:00:00 EUR 4.00 PLN\r\n\r\n\
+             2020-01-15 Salary\r\n    Assets:Bank  $100\r\n    Income:Salary  $-100\r\n"
+        );
+    }
+
+    #[test]
+    fn test_normalized() {
+        let input = r#"
+2020-02-01 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+
+P 2020-02-01 00:00:00 EUR 4.10 PLN
+
+2020-01-15 Salary
+  Assets:Bank  $100  ;  January 
+  Income:Salary
+
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let normalized = ledger.normalized();
+
+        assert_eq!(
+            normalized.to_string_pretty(&SerializerSettings::default()),
+            r#"P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+2020-01-15 Salary
+  Assets:Bank  $100  ; January
+  Income:Salary  $-100
+
+P 2020-02-01 00:00:00 EUR 4.10 PLN
+
+2020-02-01 Rent
+  Expenses:Rent  $50
+  Assets:Bank  $-50
+"#
+        );
+        // original is untouched
+        assert_eq!(ledger.transactions[0].description, "Rent");
+        assert_eq!(normalized.normalized(), normalized);
+    }
+
+    #[test]
+    fn test_validate_all() {
+        let input = r#"
+2020-01-01 Opening
+  Assets:Bank  $100
+  Equity:Opening
+
+2020-01-02 Unbalanced
+  Assets:Bank  $10
+  Expenses:Food  $5
+
+2020-01-03 Assertion
+  Assets:Bank  $-30 = $80
+  Expenses:Food
+
+2020-01-04 Wrong assertion
+  Assets:Bank  $-10 = $0
+  Expenses:Food
+"#;
+        let errors = Ledger::validate_all(input.parse().unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(errors[0], Error::UnbalancedTransaction(..)));
+        assert!(errors[0]
+            .to_string()
+            .starts_with("Unbalanced transaction (residual: $15):\n2020-01-02 Unbalanced"));
+        assert!(matches!(errors[1], Error::BalanceAssertionFailed(_)));
+        assert!(matches!(errors[2], Error::BalanceAssertionFailed(_)));
+
+        let input = r#"
+2020-01-01 Opening
+  Assets:Bank  $100
+  Equity:Opening
+"#;
+        let ledger = Ledger::validate_all(input.parse().unwrap()).unwrap();
+        assert_eq!(ledger, input.parse().unwrap());
+    }
+
+    #[test]
+    fn test_filter_by_date() {
+        let ledger: Ledger = r#"
+P 2019-12-31 00:00:00 $ 4.00 PLN
+
+2019-12-31 Old
+  Expenses:Food  $5
+  Assets:Bank
+
+2020-01-01 New Year
+  Expenses:Food  $10
+  Assets:Bank
+
+2020-12-31 Last
+  Expenses:Food  $15
+  Assets:Bank
+
+2021-01-01 Next Year
+  Expenses:Food  $20
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let descriptions = |ledger: &Ledger| -> Vec<String> {
+            ledger
+                .transactions
+                .iter()
+                .map(|txn| txn.description.clone())
+                .collect()
+        };
+
+        let year = ledger.filter_by_date(
+            NaiveDate::from_ymd_opt(2020, 1, 1),
+            NaiveDate::from_ymd_opt(2020, 12, 31),
+        );
+        assert_eq!(descriptions(&year), vec!["New Year", "Last"]);
+        assert_eq!(year.commodity_prices.len(), 1);
+
+        let until = ledger.filter_by_date(None, NaiveDate::from_ymd_opt(2020, 1, 1));
+        assert_eq!(descriptions(&until), vec!["Old", "New Year"]);
+
+        assert_eq!(ledger.filter_by_date(None, None), ledger);
+    }
+
+    #[test]
+    fn test_filter_by_account_and_description() {
+        let ledger: Ledger = r#"
+2020-01-01 Grocery Store
+  Expenses:Food  $10
+  Assets:Bank:Checking
+
+2020-01-02 Grocery Store
+  Expenses:Food  $5
+  Assets:Cash
+
+2020-01-03 Landlord
+  Expenses:Rent  $50
+  Assets:Bank:Checking
+"#
+        .parse()
+        .unwrap();
+
+        let checking = ledger.filter_by_account(|account| account == "Assets:Bank:Checking");
+        assert_eq!(checking.transactions.len(), 2);
+        assert_eq!(checking.transactions[0].postings.len(), 2);
+
+        let groceries_from_checking = checking.filter_by_description("Grocery");
+        assert_eq!(groceries_from_checking.transactions.len(), 1);
+        assert_eq!(
+            groceries_from_checking.transactions[0].date,
+            NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_posting() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Restaurant
+  Expenses:Food  $100.00  ; dinner
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let transaction = &mut ledger.transactions[0];
+        transaction.split_posting(
+            0,
+            &[
+                ("Expenses:Food".to_string(), Decimal::ONE),
+                ("Assets:Receivable:Alice".to_string(), Decimal::ONE),
+                ("Assets:Receivable:Bob".to_string(), Decimal::ONE),
+            ],
+        );
+
+        let expected: Ledger = r#"
+2020-01-15 Restaurant
+  Expenses:Food  $33.33  ; dinner
+  Assets:Receivable:Alice  $33.33  ; dinner
+  Assets:Receivable:Bob  $33.34  ; dinner
+  Assets:Bank  $-100.00
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(ledger, expected);
+
+        // total prices are split like amounts, so the transaction still balances
+        let mut ledger: Ledger = r#"
+2020-01-15 Restaurant
+  Expenses:Food  10 EUR @@ 43.00 PLN
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        ledger.transactions[0].split_posting(
+            0,
+            &[
+                ("Expenses:Food".to_string(), Decimal::ONE),
+                ("Assets:Receivable:Alice".to_string(), Decimal::TWO),
+            ],
+        );
+        let output = ledger.transactions[0].to_string();
+        assert_eq!(
+            output,
+            r#"2020-01-15 Restaurant
+  Expenses:Food  3 EUR @@ 12.90 PLN
+  Assets:Receivable:Alice  7 EUR @@ 30.10 PLN
+  Assets:Bank  -43.00 PLN"#
+        );
+        assert_eq!(
+            output.parse::<Ledger>().unwrap().transactions,
+            ledger.transactions
+        );
+    }
+
+    #[test]
+    fn test_consolidate_postings() {
+        let input = r#"
+2020-01-15 Shopping
+  Expenses:Food  $10  ; bread
+  Expenses:Food  $5  ; milk
+  Expenses:Food  3 EUR
+  Expenses:Food  $2  ; [2020-01-16]
+  Assets:Bank  $-17
+  Assets:Bank  -3 EUR
+"#;
+        let mut ledger: Ledger = input.parse().unwrap();
+        ledger.transactions[0].consolidate_postings_with_same_metadata();
+        let postings: Vec<_> = ledger.transactions[0]
+            .postings
+            .iter()
+            .map(|posting| (posting.amount.to_string(), posting.comment.clone()))
+            .collect();
+        assert_eq!(
+            postings,
+            vec![
+                ("$15".to_string(), Some("bread\nmilk".to_string())),
+                ("3 EUR".to_string(), None),
+                ("$2".to_string(), None),
+                ("$-17".to_string(), None),
+                ("-3 EUR".to_string(), None),
+            ]
+        );
+
+        let mut ledger: Ledger = input.parse().unwrap();
+        ledger.transactions[0].consolidate_postings();
+        let amounts: Vec<_> = ledger.transactions[0]
+            .postings
+            .iter()
+            .map(|posting| posting.amount.to_string())
+            .collect();
+        assert_eq!(amounts, vec!["$17", "3 EUR", "$-17", "-3 EUR"]);
+        assert_eq!(
+            crate::balance::Balance::from(&ledger.transactions[0])
+                .get_account_balance(&[""])
+                .to_string(),
+            "0"
+        );
+    }
+
+    #[test]
+    fn test_apply_commodity_aliases() {
+        let mut ledger: Ledger = r#"
+P 2019-01-01 00:00:00 USD 1 $
+P 2019-01-01 00:00:00 EUR 1.10 USD
+
+2019-01-15 Salary
+  Assets:Bank  100 USD
+  Income:Salary
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+
+        ledger.rename_commodity("USD", "$");
+
+        let balance = crate::balance::Balance::from(&ledger);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$200");
+        assert_eq!(ledger.commodity_prices.len(), 1);
+        assert_eq!(ledger.commodity_prices[0].amount.to_string(), "$1.10");
+    }
+
+    #[test]
+    fn test_validate_posting_dates() {
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+        let mut ledger: Ledger = r#"
+2020-01-10=2020-01-12 Shopping
+  Expenses:Food  $10
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        // (posting date, posting effective date) around the transaction dates (10, 12)
+        for (posting_date, posting_effective_date) in [
+            (10, 12),
+            (10, 10),
+            (10, 11),
+            (11, 11),
+            (11, 12),
+            (11, 10),
+            (12, 12),
+            (12, 10),
+        ] {
+            let transaction = &mut ledger.transactions[0];
+            transaction.postings[0].date = date(posting_date);
+            transaction.postings[0].effective_date = date(posting_effective_date);
+            assert_eq!(transaction.validate_posting_dates(), Ok(()));
+
+            let reparsed: Ledger = ledger.to_string().parse().unwrap();
+            assert_eq!(reparsed, ledger, "{}", ledger);
+        }
+
+        // comment lines looking like date annotations are parsed as dates
+        let transaction = &mut ledger.transactions[0];
+        transaction.postings[0].date = date(10);
+        transaction.postings[0].effective_date = date(12);
+        transaction.postings[0].comment = Some("paid\n[=2020-02-02]".to_string());
+        assert_eq!(
+            transaction.validate_posting_dates(),
+            Err(Error::InconsistentPostingDates(transaction.clone().into()))
+        );
+        transaction.postings[0].comment = Some("paid [=2020-02-02]".to_string());
+        assert_eq!(transaction.validate_posting_dates(), Ok(()));
+    }
+
+    #[test]
+    fn test_imbalance() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Shopping
+  Expenses:Food  $10
+  (Budget:Food)  $-10
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        assert!(ledger.transactions[0].imbalance().amounts.is_empty());
+
+        ledger.transactions[0].postings[0].amount.quantity = Decimal::new(1003, 2);
+        assert_eq!(ledger.transactions[0].imbalance().to_string(), "$0.03");
+    }
+
+    #[test]
+    fn test_validate_declared() {
+        let input = r#"commodity $
+account Assets:Bank  ; checking
+account Expenses:Food
+
+2020-01-15 Shopping
+  Expenses:Food  $10
+  Assets:Bank  $-10
+"#;
+        let mut ledger: Ledger = input.parse().unwrap();
+        assert_eq!(
+            ledger.declared_accounts,
+            vec!["Assets:Bank", "Expenses:Food"]
+        );
+        assert_eq!(ledger.declared_commodities, vec!["$"]);
+        assert!(ledger.validate_declared().is_ok());
+
+        // comments of the directives are not kept
+        let output = ledger.to_string();
+        assert_eq!(output, input.replace("  ; checking", ""));
+        assert_eq!(output.parse::<Ledger>().unwrap(), ledger);
+
+        ledger.declared_accounts.pop();
+        assert!(matches!(
+            ledger.validate_declared(),
+            Err(Error::UndeclaredAccount(account)) if account == "Expenses:Food"
+        ));
+    }
+
+    #[test]
+    fn test_rename_account() {
+        let mut ledger: Ledger = r#"
+2020-01-15 Shopping
+  Expenses:Food  $10
+  Expenses:Food:Lunch  $5
+  Expenses:Foodstuff  $1
+  Expenses:Groceries  $20
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+        let total = crate::balance::Balance::from(&ledger).get_account_balance(&["Expenses"]);
+
+        ledger.rename_account("Expenses:Food", "Expenses:Groceries");
+        let balance = crate::balance::Balance::from(&ledger);
+        assert_eq!(
+            balance.account_balances["Expenses:Groceries"].to_string(),
+            "$30"
+        );
+        assert_eq!(
+            balance.account_balances["Expenses:Food:Lunch"].to_string(),
+            "$5"
+        );
+
+        ledger.rename_account_prefix("Expenses:Food", "Expenses:Groceries");
+        let balance = crate::balance::Balance::from(&ledger);
+        assert_eq!(
+            balance.get_account_balance(&["Expenses"]).to_string(),
+            total.to_string()
+        );
+
+        let tree = crate::tree_balance::TreeBalanceNode::from(balance);
+        let expenses = &tree.children["Expenses"];
+        let mut children: Vec<_> = expenses.children.keys().collect();
+        children.sort();
+        assert_eq!(children, vec!["Foodstuff", "Groceries"]);
+        assert_eq!(expenses.children["Groceries"].balance.to_string(), "$35");
+        assert_eq!(
+            expenses.children["Groceries"].children["Lunch"]
+                .balance
+                .to_string(),
+            "$5"
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let ledger: Ledger = r#"
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+
+2020-02-01 Rent
+  Expenses:Rent  $50
+  Assets:Bank
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary
+"#
+        .parse()
+        .unwrap();
+
+        let stats = ledger.stats();
+        assert_eq!(stats.first_date, NaiveDate::from_ymd_opt(2020, 1, 15));
+        assert_eq!(stats.last_date, NaiveDate::from_ymd_opt(2020, 2, 1));
+        assert_eq!(stats.transaction_count, 2);
+        assert_eq!(stats.posting_count, 4);
+        assert_eq!(
+            stats.accounts.iter().collect::<Vec<_>>(),
+            vec!["Assets:Bank", "Expenses:Rent", "Income:Salary"]
+        );
+        assert_eq!(
+            stats.commodities.iter().collect::<Vec<_>>(),
+            vec!["$", "EUR", "PLN"]
+        );
+
+        assert_eq!(
+            Ledger::from_str("").unwrap().stats(),
+            LedgerStats::default()
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        let ledger: Ledger = r#"
+2020-01-01 Hotel
+  Expenses:Travel  $100  ; :reimbursable:
+  Assets:Bank  ; project: foo
+
+2020-01-03 Taxi
+  Expenses:Travel  $20  ; project: bar
+  Assets:Bank  ; project: foo
+"#
+        .parse()
+        .unwrap();
+
+        let reimbursable = ledger.postings_with_tag("reimbursable");
+        assert_eq!(reimbursable.len(), 1);
+        assert_eq!(reimbursable[0].0.description, "Hotel");
+
+        let foo = TagValue::String("foo".to_string());
+        let postings: Vec<_> = ledger
+            .postings_with_tag_value("project", &foo)
+            .iter()
+            .map(|(txn, posting)| (txn.description.as_str(), posting.amount.to_string()))
+            .collect();
+        assert_eq!(
+            postings,
+            vec![("Hotel", "$-100".to_string()), ("Taxi", "$-20".to_string())]
+        );
+
+        assert_eq!(
+            ledger.tag_values("project"),
+            vec![&foo, &TagValue::String("bar".to_string())]
+        );
+        assert!(ledger.tag_values("reimbursable").is_empty());
+    }
+
+    #[test]
+    fn test_default_commodity() {
+        let input = r#"D $1,000.00
+
+2020-01-01 Opening balance
+  Assets:Bank  $100
+  Equity:Opening
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        assert_eq!(
+            ledger.default_commodity,
+            Some(DefaultCommodity {
+                amount: Amount {
+                    quantity: Decimal::new(100000, 2),
+                    commodity: Commodity {
+                        name: "$".to_string(),
+                        position: CommodityPosition::Left,
+                    },
+                },
+                text: "$1,000.00".to_string(),
+            })
+        );
+
+        let output = ledger.to_string();
+        assert_eq!(
+            output,
+            r#"D $1,000.00
+
+2020-01-01 Opening balance
+  Assets:Bank  $100
+  Equity:Opening  $-100
+"#
+        );
+        assert_eq!(output.parse::<Ledger>().unwrap(), ledger);
+
+        // line numbers of parse errors don't change
+        let err = "D $1.00\n\n2020-01-01 X\n  A  $1\n  B  $1 $1\n"
+            .parse::<Ledger>()
+            .unwrap_err();
+        let plain_err = "\n\n2020-01-01 X\n  A  $1\n  B  $1 $1\n"
+            .parse::<Ledger>()
+            .unwrap_err();
+        assert_eq!(err, plain_err);
+    }
+
+    #[test]
+    fn test_into_ledger_parser_ledger() {
+        let ledger: Ledger = r#"
+; header
+
+P 2020-01-01 00:00:00 EUR 4.00 PLN
+P 2020-01-01 00:00:00 USD 3.50 PLN
+
+2020-01-01=2020-01-02 * (1) Hotel
+  ; trip: Berlin
+  Expenses:Travel  100 PLN  ; [2020-01-03]
+  ; :reimbursable:
+  [Budget:Travel]  -100 PLN
+  [Assets:Budget]  100 PLN
+  Assets:Bank
+
+2020-01-05 Exchange
+  Assets:Savings  100 EUR @ 4.10 PLN
+  Assets:Broker  2 AAPL {{$200}} @@ 820 PLN
+  Assets:Bank
+
+; footer
+"#
+        .parse()
+        .unwrap();
+        assert_eq!(ledger.commodity_prices.len(), 4);
+
+        let converted = ledger_parser::Ledger::from(ledger.clone());
+        assert!(matches!(
+            converted.items[..3],
+            [
+                LedgerItem::LineComment(_),
+                LedgerItem::EmptyLine,
+                LedgerItem::CommodityPrice(_)
+            ]
+        ));
+        assert_eq!(Ledger::try_from(converted).unwrap(), ledger);
+        assert_eq!(ledger.to_string().parse::<Ledger>().unwrap(), ledger);
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let input = r#"
+2020-01-01 Hotel
+  ; trip: Berlin
+  ; :work:
+  Expenses:Travel  $100  ; :reimbursable:
+  ; night:: 2
+  ; :receipt:scanned:
+  ; paid by card
+  Assets:Bank  ; [2020-01-02=2020-01-03]
+  ; project: foo
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        let output = ledger.to_string();
+        assert_eq!(
+            output,
+            r#"2020-01-01 Hotel
+  ; trip: Berlin
+  ; :work:
+  Expenses:Travel  $100  ; paid by card
+  ; :reimbursable:
+  ; night:: 2
+  ; :receipt:scanned:
+  Assets:Bank  $-100  ; [2020-01-02=2020-01-03]
+  ; project: foo
+"#
+        );
+
+        let reparsed: Ledger = output.parse().unwrap();
+        assert_eq!(reparsed, ledger);
+        assert_eq!(reparsed.to_string(), output);
+    }
+
+    #[test]
+    fn test_postings() {
+        let ledger: Ledger = r#"
+2020-01-01 Grocery Store
+  Expenses:Food  $10  ; [2020-01-02]
+  Assets:Bank
+
+2020-01-03 Landlord
+  Expenses:Rent  $50
+  Assets:Bank
+"#
+        .parse()
+        .unwrap();
+
+        let postings: Vec<_> = ledger
+            .postings()
+            .map(|(date, txn, posting)| {
+                (
+                    date.to_string(),
+                    txn.description.as_str(),
+                    posting.account.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            postings,
+            vec![
+                ("2020-01-02".to_string(), "Grocery Store", "Expenses:Food"),
+                ("2020-01-01".to_string(), "Grocery Store", "Assets:Bank"),
+                ("2020-01-03".to_string(), "Landlord", "Expenses:Rent"),
+                ("2020-01-03".to_string(), "Landlord", "Assets:Bank"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_file() {
+        let dir = std::env::temp_dir().join(format!("ledger-utils-load-{}", std::process::id()));
+        fs::create_dir_all(dir.join("accounts")).unwrap();
+        fs::write(
+            dir.join("main.ledger"),
+            "include accounts/bank.ledger\n\n2020-01-02 Rent\n  Expenses:Rent  $50\n  Assets:Bank\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("accounts/bank.ledger"),
+            "include ../prices.ledger\n\n2020-01-01 Salary\n  Assets:Bank  $100\n  Income:Salary\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("prices.ledger"),
+            "P 2020-01-01 00:00:00 $ 4.00 PLN\n",
+        )
+        .unwrap();
+
+        let ledger = Ledger::load_file(&dir.join("main.ledger")).unwrap();
+        assert_eq!(ledger.commodity_prices.len(), 1);
+        let descriptions: Vec<_> = ledger
+            .transactions
+            .iter()
+            .map(|txn| txn.description.as_str())
+            .collect();
+        assert_eq!(descriptions, vec!["Salary", "Rent"]);
+
+        fs::write(dir.join("prices.ledger"), "include main.ledger\n").unwrap();
+        assert_eq!(
+            Ledger::load_file(&dir.join("main.ledger")),
+            Err(Error::IncludeCycle(dir.join("accounts/../main.ledger")))
+        );
+
+        fs::write(dir.join("prices.ledger"), "include missing.ledger\n").unwrap();
+        let err = Ledger::load_file(&dir.join("main.ledger")).unwrap_err();
+        assert!(err.to_string().contains("missing.ledger"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn display_ledger_interleaved_prices() {
+        let input = r#"P 2020-01-01 00:00:00 $ 4.00 PLN
+P 2020-01-01 00:00:00 EUR 4.50 PLN
+
+2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary  $-100
+
+P 2020-02-01 00:00:00 $ 4.10 PLN
+
+2020-02-10 Rent
+  Expenses:Rent  $50
+  Assets:Bank  $-50
+
+P 2020-03-01 00:00:00 $ 4.20 PLN
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        assert_eq!(ledger.to_string(), input);
+    }
+
+    #[test]
+    fn display_ledger_standalone_comments() {
+        let input = r#"; Personal finances
+
+; Prices
+
+P 2020-01-01 00:00:00 $ 4.00 PLN
+
+2020-01-15 Salary
+  ; Salary comment
+  Assets:Bank  $100
+  Income:Salary  $-100
+
+P 2020-02-01 00:00:00 $ 4.10 PLN
+
+; End of file
+; second line
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        assert_eq!(ledger.comments.len(), 3);
+        assert_eq!(
+            ledger.transactions[0].comment,
+            Some("Salary comment".to_string())
+        );
+        assert_eq!(ledger.comments[2].date, None);
+        assert_eq!(ledger.to_string(), input);
+    }
+
+    #[test]
+    fn display_ledger_trailing_comment_after_transaction() {
+        let input = r#"2020-01-15 Salary
+  Assets:Bank  $100
+  Income:Salary  $-100
+
+; End of file
+"#;
+        let ledger: Ledger = input.parse().unwrap();
+        assert_eq!(ledger.to_string(), input);
+    }
+
     #[test]
     fn display_ledger() {
         let actual = format!(
@@ -499,6 +2487,7 @@ mod tests {
                         status: Some(TransactionStatus::Pending),
                         code: Some("123".to_string()),
                         description: "Marek Ogarek".to_string(),
+                        tags: vec![],
                         postings: vec![
                             Posting {
                                 date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
@@ -512,6 +2501,9 @@ mod tests {
                                         position: CommodityPosition::Left
                                     }
                                 },
+                                lot_price: None,
+                                price: None,
+                                balance: None,
                                 status: None,
                                 comment: Some("dd".to_string()),
                                 tags: vec![],
@@ -528,6 +2520,9 @@ mod tests {
                                         position: CommodityPosition::Left
                                     }
                                 },
+                                lot_price: None,
+                                price: None,
+                                balance: None,
                                 status: None,
                                 comment: None,
                                 tags: vec![
@@ -550,6 +2545,7 @@ mod tests {
                         status: None,
                         code: None,
                         description: "Marek Ogarek".to_string(),
+                        tags: vec![],
                         postings: vec![
                             Posting {
                                 date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
@@ -563,6 +2559,9 @@ mod tests {
                                         position: CommodityPosition::Left
                                     }
                                 },
+                                lot_price: None,
+                                price: None,
+                                balance: None,
                                 status: None,
                                 comment: None,
                                 tags: vec![Tag {
@@ -584,6 +2583,9 @@ mod tests {
                                         position: CommodityPosition::Left
                                     }
                                 },
+                                lot_price: None,
+                                price: None,
+                                balance: None,
                                 status: None,
                                 comment: None,
                                 tags: vec![],
@@ -604,7 +2606,11 @@ mod tests {
                             position: CommodityPosition::Right
                         }
                     }
-                }]
+                }],
+                comments: vec![],
+                default_commodity: None,
+                declared_accounts: vec![],
+                declared_commodities: vec![],
             }
         );
         let expected = r#"P 2017-11-12 12:00:00 mBH 5.00 PLN
@@ -0,0 +1,16 @@
+//! Helpers shared by the unit tests.
+
+use crate::prices::Prices;
+use chrono::NaiveDate;
+
+pub fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+/// Prices from the `P` directives and priced postings of a ledger file.
+pub fn prices_from(input: &str) -> Prices {
+    let ledger: ledger_parser::Ledger = input.parse().unwrap();
+    let mut prices = Prices::new();
+    prices.insert_from(&ledger);
+    prices
+}
@@ -1,15 +1,28 @@
 use crate::account_balance::AccountBalance;
 use crate::balance::Balance;
+use crate::prices::{Prices, PricesError};
+use chrono::NaiveDate;
+use ledger_parser::{Serializer, SerializerSettings};
+use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Balance of one or more accounts.
 /// Converted to a tree.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TreeBalanceNode {
     pub balance: AccountBalance,
     pub children: HashMap<String, TreeBalanceNode>,
 }
 
+/// Value of a `TreeBalanceNode` subtree in a single commodity.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValuedTreeNode {
+    pub value: Decimal,
+    pub children: HashMap<String, ValuedTreeNode>,
+}
+
 impl TreeBalanceNode {
     pub fn new() -> Self {
         TreeBalanceNode {
@@ -17,6 +30,214 @@ impl TreeBalanceNode {
             children: HashMap::new(),
         }
     }
+
+    /// Children sorted by account segment.
+    pub fn sorted_children(&self) -> Vec<(&String, &TreeBalanceNode)> {
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_by_key(|&(name, _)| name);
+        children
+    }
+
+    /// Depth-first iterator over all descendants (the root itself is skipped),
+    /// yielding full account names in sorted order.
+    pub fn iter(&self) -> TreeBalanceIter<'_> {
+        let mut iter = TreeBalanceIter { stack: Vec::new() };
+        iter.push_children(self, "");
+        iter
+    }
+
+    /// Values the node's balance (including all children) in `commodity_name`.
+    pub fn value_in_commodity(
+        &self,
+        commodity_name: &str,
+        date: NaiveDate,
+        prices: &Prices,
+    ) -> Result<Decimal, PricesError> {
+        self.balance
+            .value_in_commodity(commodity_name, date, prices)
+    }
+
+    /// Builds a parallel tree with every node valued in `commodity_name`.
+    pub fn valued_tree(
+        &self,
+        commodity_name: &str,
+        date: NaiveDate,
+        prices: &Prices,
+    ) -> Result<ValuedTreeNode, PricesError> {
+        let mut children = HashMap::new();
+        for (name, child) in &self.children {
+            children.insert(
+                name.clone(),
+                child.valued_tree(commodity_name, date, prices)?,
+            );
+        }
+
+        Ok(ValuedTreeNode {
+            value: self.value_in_commodity(commodity_name, date, prices)?,
+            children,
+        })
+    }
+
+    /// Collapses accounts deeper than `depth` into their ancestor at `depth`.
+    /// The root is at depth 0, so depth 1 gives top-level accounts only.
+    pub fn collapsed_to_depth(&self, depth: usize) -> Balance {
+        let mut balance = Balance::new();
+        self.collapse_into(&mut balance, "", depth);
+        balance
+    }
+
+    /// Renders the tree as an indented text report (like `ledger balance`).
+    ///
+    /// Every account is printed with its subtotal, children are indented
+    /// by `settings.indent` per level and sorted by name.
+    pub fn to_report_string(&self, settings: &SerializerSettings) -> String {
+        let mut lines = Vec::new();
+        self.collect_report_lines(&mut lines, "", settings);
+
+        let width = lines
+            .iter()
+            .map(|(amount, _)| amount.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut result = String::new();
+        for (amount, label) in lines {
+            if label.is_empty() {
+                result.push_str(&format!("{:>width$}", amount));
+            } else {
+                result.push_str(&format!("{:>width$}  {}", amount, label));
+            }
+            result.push_str(&settings.eol);
+        }
+        result
+    }
+
+    fn collect_report_lines(
+        &self,
+        lines: &mut Vec<(String, String)>,
+        prefix: &str,
+        settings: &SerializerSettings,
+    ) {
+        for (name, child) in self.sorted_children() {
+            let amounts = child.balance.sorted_amounts();
+            if amounts.is_empty() {
+                lines.push(("0".to_string(), format!("{}{}", prefix, name)));
+            }
+            for (i, amount) in amounts.into_iter().enumerate() {
+                let label = if i == 0 {
+                    format!("{}{}", prefix, name)
+                } else {
+                    String::new()
+                };
+                lines.push((amount.to_string_pretty(settings), label));
+            }
+
+            child.collect_report_lines(lines, &format!("{}{}", prefix, settings.indent), settings);
+        }
+    }
+
+    /// Recursively removes children whose subtree balance is zero.
+    pub fn prune_zero(&mut self) {
+        self.children.retain(|_, child| !child.balance.is_zero());
+        for child in self.children.values_mut() {
+            child.prune_zero();
+        }
+    }
+
+    /// Recursively removes children whose subtree balance is worth
+    /// less than `epsilon` (in absolute value) in `commodity_name`.
+    pub fn prune_below(
+        &mut self,
+        commodity_name: &str,
+        epsilon: Decimal,
+        date: NaiveDate,
+        prices: &Prices,
+    ) -> Result<(), PricesError> {
+        let mut pruned = Vec::new();
+        for (name, child) in &self.children {
+            let value = child
+                .balance
+                .value_in_commodity(commodity_name, date, prices)?;
+            if value.abs() < epsilon {
+                pruned.push(name.clone());
+            }
+        }
+        for name in pruned {
+            self.children.remove(&name);
+        }
+
+        for child in self.children.values_mut() {
+            child.prune_below(commodity_name, epsilon, date, prices)?;
+        }
+        Ok(())
+    }
+
+    fn collapse_into(&self, balance: &mut Balance, account_name: &str, depth: usize) {
+        let mut own_balance = self.balance.clone();
+        if depth > 0 {
+            for (name, child) in &self.children {
+                let child_account_name = if account_name.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}:{}", account_name, name)
+                };
+                child.collapse_into(balance, &child_account_name, depth - 1);
+                own_balance -= &child.balance;
+            }
+        }
+
+        if !own_balance.is_zero() {
+            balance
+                .account_balances
+                .insert(account_name.to_string(), own_balance);
+        }
+    }
+
+    fn flatten_into(self, balance: &mut Balance, account_name: String) {
+        if self.children.is_empty() {
+            if !account_name.is_empty() {
+                balance.account_balances.insert(account_name, self.balance);
+            }
+            return;
+        }
+
+        for (name, child) in self.children {
+            let child_account_name = if account_name.is_empty() {
+                name
+            } else {
+                format!("{}:{}", account_name, name)
+            };
+            child.flatten_into(balance, child_account_name);
+        }
+    }
+}
+
+/// Iterator returned by `TreeBalanceNode::iter`.
+pub struct TreeBalanceIter<'a> {
+    stack: Vec<(String, &'a TreeBalanceNode)>,
+}
+
+impl<'a> TreeBalanceIter<'a> {
+    fn push_children(&mut self, node: &'a TreeBalanceNode, account_name: &str) {
+        for (name, child) in node.sorted_children().into_iter().rev() {
+            let child_account_name = if account_name.is_empty() {
+                name.clone()
+            } else {
+                format!("{}:{}", account_name, name)
+            };
+            self.stack.push((child_account_name, child));
+        }
+    }
+}
+
+impl<'a> Iterator for TreeBalanceIter<'a> {
+    type Item = (String, &'a AccountBalance);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (account_name, node) = self.stack.pop()?;
+        self.push_children(node, &account_name);
+        Some((account_name, &node.balance))
+    }
 }
 
 impl Default for TreeBalanceNode {
@@ -43,3 +264,224 @@ impl From<Balance> for TreeBalanceNode {
         root
     }
 }
+
+/// Emits only leaf balances, with account names rebuilt from the path.
+///
+/// Parent aggregates are intentionally dropped, so amounts posted directly
+/// to a non-leaf account (e.g. both `Assets:Bank` and `Assets:Bank:Savings`)
+/// are lost. Use `collapsed_to_depth(usize::MAX)` to keep them.
+impl From<TreeBalanceNode> for Balance {
+    fn from(tree: TreeBalanceNode) -> Self {
+        let mut balance = Balance::new();
+        tree.flatten_into(&mut balance, String::new());
+        balance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{date, prices_from};
+    use crate::Ledger;
+
+    fn tree_from(input: &str) -> TreeBalanceNode {
+        let ledger: Ledger = input.parse().unwrap();
+        Balance::from(&ledger).into()
+    }
+
+    #[test]
+    fn test_collapsed_to_depth() {
+        let tree = tree_from(
+            r#"
+2020-01-15 Salary
+  Assets:Bank:Checking  $100
+  Assets:Bank:Savings  $50
+  Assets:Cash  $10
+  Income:Salary
+"#,
+        );
+
+        let balance = tree.collapsed_to_depth(1);
+        let mut accounts: Vec<_> = balance
+            .account_balances
+            .iter()
+            .map(|(name, balance)| (name.as_str(), balance.to_string()))
+            .collect();
+        accounts.sort();
+        assert_eq!(
+            accounts,
+            vec![
+                ("Assets", "$160".to_string()),
+                ("Income", "$-160".to_string())
+            ]
+        );
+
+        let balance = tree.collapsed_to_depth(2);
+        assert_eq!(balance.account_balances.len(), 3);
+        assert_eq!(balance.account_balances["Assets:Bank"].to_string(), "$150");
+        assert_eq!(
+            balance.account_balances["Income:Salary"].to_string(),
+            "$-160"
+        );
+    }
+
+    #[test]
+    fn test_iter() {
+        let tree = tree_from(
+            r#"
+2020-01-15 Salary
+  Assets:Cash  $10
+  Assets:Bank:Savings  $50
+  Assets:Bank:Checking  $100
+  Income:Salary
+"#,
+        );
+
+        let names: Vec<_> = tree.children["Assets"]
+            .sorted_children()
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Bank", "Cash"]);
+
+        let entries: Vec<_> = tree
+            .iter()
+            .map(|(name, balance)| (name, balance.to_string()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("Assets".to_string(), "$160".to_string()),
+                ("Assets:Bank".to_string(), "$150".to_string()),
+                ("Assets:Bank:Checking".to_string(), "$100".to_string()),
+                ("Assets:Bank:Savings".to_string(), "$50".to_string()),
+                ("Assets:Cash".to_string(), "$10".to_string()),
+                ("Income".to_string(), "$-160".to_string()),
+                ("Income:Salary".to_string(), "$-160".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_valued_tree() {
+        let tree = tree_from(
+            r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Assets:Cash  10 EUR
+  Income:Salary
+"#,
+        );
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR $1.10\n");
+        let date = date(2020, 1, 15);
+
+        assert_eq!(
+            tree.children["Assets"]
+                .value_in_commodity("$", date, &prices)
+                .unwrap(),
+            Decimal::new(1110, 1)
+        );
+
+        let valued = tree.valued_tree("$", date, &prices).unwrap();
+        assert_eq!(valued.value, Decimal::ZERO);
+        assert_eq!(
+            valued.children["Assets"].children["Cash"].value,
+            Decimal::new(110, 1)
+        );
+        assert!(tree.valued_tree("PLN", date, &prices).is_err());
+    }
+
+    #[test]
+    fn test_into_balance() {
+        let ledger: Ledger = r#"
+2020-01-15 Salary
+  Assets:Bank:Checking  $100
+  Assets:Bank:Savings  $50
+  Assets:Cash  10 EUR
+  Income:Salary  $-150
+  Income:Salary  -10 EUR
+"#
+        .parse()
+        .unwrap();
+        let balance = Balance::from(&ledger);
+
+        let tree = TreeBalanceNode::from(balance.clone());
+        let round_trip = Balance::from(tree);
+        assert!(round_trip.diff(&balance).is_empty());
+        assert_eq!(round_trip.account_balances.len(), 4);
+    }
+
+    #[test]
+    fn test_to_report_string() {
+        let tree = tree_from(
+            r#"
+2020-01-15 Salary
+  Assets:Bank:Checking  $100
+  Assets:Bank:Savings  $50
+  Assets:Cash  10 EUR
+  Income:Salary  $-150
+  Income:Salary  -10 EUR
+"#,
+        );
+
+        let expected = r#"   $150  Assets
+ 10 EUR
+   $150    Bank
+   $100      Checking
+    $50      Savings
+ 10 EUR    Cash
+  $-150  Income
+-10 EUR
+  $-150    Salary
+-10 EUR
+"#;
+        assert_eq!(
+            tree.to_report_string(&SerializerSettings::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_prune_zero() {
+        let mut tree = tree_from(
+            r#"
+2020-01-15 Transfer
+  Assets:Bank:A  $10
+  Assets:Bank:B  $-10
+  Assets:Cash  $5
+  Liabilities:Card  $5
+  Liabilities:Loan  $-5
+  Equity  $-5
+"#,
+        );
+
+        tree.prune_zero();
+        let mut names: Vec<_> = tree.children.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["Assets", "Equity"]);
+        assert_eq!(
+            tree.children["Assets"].children.keys().collect::<Vec<_>>(),
+            vec!["Cash"]
+        );
+    }
+
+    #[test]
+    fn test_prune_below() {
+        let mut tree = tree_from(
+            r#"
+2020-01-15 Salary
+  Assets:Bank  $100
+  Assets:Cash  0.01 EUR
+  Income:Salary
+"#,
+        );
+        let prices = prices_from("P 2020-01-01 00:00:00 EUR $1.10\n");
+
+        tree.prune_below("$", Decimal::new(1, 0), date(2020, 1, 15), &prices)
+            .unwrap();
+        assert_eq!(
+            tree.children["Assets"].children.keys().collect::<Vec<_>>(),
+            vec!["Bank"]
+        );
+    }
+}